@@ -1,26 +1,62 @@
 use anchor_lang::Discriminator;
 use anyhow::anyhow;
+use base64::prelude::{Engine, BASE64_STANDARD};
 use borsh::BorshDeserialize;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use env_logger::WriteStyle;
+use futures_util::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, error, info, log_enabled, trace, warn, Level, LevelFilter};
+use serde::{Deserialize, Serialize};
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
 use solana_cli_config::{Config, CONFIG_FILE};
-use solana_client::client_error::ClientErrorKind;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::nonblocking::pubsub_client::PubsubClient;
 use solana_client::nonblocking::rpc_client::RpcClient;
-use solana_client::rpc_config::RpcTransactionConfig;
-use solana_client::rpc_request::{RpcError, RpcResponseErrorData};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSendTransactionConfig, RpcTransactionConfig,
+};
+use solana_client::rpc_custom_error::JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED;
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_client::rpc_request::{RpcError, RpcResponseErrorData, MAX_MULTIPLE_ACCOUNTS};
+use solana_client::rpc_response::RpcConfirmedTransactionStatusWithSignature;
+use solana_sdk::account::{from_account, Account};
+use solana_sdk::clock::Slot;
 use solana_sdk::commitment_config::CommitmentConfig;
-use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::{Instruction, InstructionError};
+use solana_sdk::message::Message;
+use solana_sdk::native_token::lamports_to_sol;
+use solana_sdk::program_error::ProgramError;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::rent::Rent;
 use solana_sdk::signature::Signature;
 use solana_sdk::signer::keypair::{read_keypair_file, Keypair};
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::Transaction;
-use solana_sdk::{bpf_loader_upgradeable, system_program};
-use solana_transaction_status::UiTransactionEncoding;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use solana_sdk::{bpf_loader_upgradeable, bs58, system_program, sysvar};
+use solana_transaction_status::option_serializer::OptionSerializer;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
 use squads_mpl::state::Ms;
-use std::io::Write;
+use squads_v3_index::squads_mpl::{MS_KEYS_LEN_OFFSET, MS_KEYS_OFFSET};
+use squads_v3_index::{
+    close_accounts, close_instruction, derive_squads_authority_with_program_id, find_index_address,
+    index_accounts, index_instruction, IndexAccount, IndexError, IndexInstruction,
+    INDEX_ACCOUNT_SIZE, INDEX_ACCOUNT_VERSION,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use std::vec;
+use tokio::sync::Notify;
 
 #[derive(Parser)]
 #[command(author, version, about)]
@@ -30,12 +66,190 @@ struct Args {
     /// Optionally include your RPC endpoint. Use "local", "dev", "main" for default endpoints. Defaults to your Solana CLI config file.
     #[clap(global = true, short, long)]
     url: Option<String>,
-    /// Optionally include your keypair path. Defaults to your Solana CLI config file.
+    /// Optionally include your keypair path. Defaults to your Solana CLI config file. Use
+    /// `env:VAR` to read the keypair JSON from an environment variable, or `-` for stdin.
     #[clap(global = true, short, long)]
     keypair_path: Option<String>,
-    /// Optionally include your RPC endpoint. Use "local", "dev", "main" for default endpoints. Defaults to your Solana CLI config file.
+    /// Keypair path of a separate account that pays transaction fees and co-signs. The keypair
+    /// from --keypair-path still authorizes and funds the index accounts.
+    #[clap(global = true, long, value_name = "KEYPAIR_PATH")]
+    fee_payer: Option<String>,
+    /// Keypairs that take turns paying the transaction fees of a batch: a directory of keypair
+    /// JSON files or a comma separated list of keypair paths.
+    #[clap(
+        global = true,
+        long,
+        value_name = "DIR_OR_LIST",
+        conflicts_with = "fee_payer"
+    )]
+    keypair_pool: Option<String>,
+    /// Skip confirmation prompts and execute immediately. Without it, only transactions that will
+    /// be sent are confirmed: a command with nothing to do, e.g. for an address that is already
+    /// indexed, never prompts.
     #[clap(global = true, short, long, default_value = "false")]
     yes: bool,
+    /// Send transactions without running a preflight simulation first.
+    #[clap(global = true, long)]
+    skip_preflight: bool,
+    /// Print debug diagnostics. Pass twice for trace output.
+    #[clap(global = true, short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Only print errors. Command results are still written to stdout.
+    #[clap(global = true, short, long)]
+    quiet: bool,
+    /// Print plain text without emoji or ANSI colors. Also enabled by the NO_COLOR environment
+    /// variable.
+    #[clap(global = true, long)]
+    no_color: bool,
+    /// Address of the index program. Defaults to the mainnet deployment.
+    #[clap(global = true, long)]
+    program_id: Option<Pubkey>,
+    /// Address of the Squads V3 program that owns the multisigs and derives their authorities.
+    /// Defaults to the mainnet deployment, SMPLecH534NA9acpos4G6x7uf3LWbCAwZQE9e8ZekMu.
+    #[clap(global = true, long)]
+    squads_program_id: Option<Pubkey>,
+    /// Commitment level for RPC requests. Defaults to confirmed.
+    #[clap(global = true, long, value_enum)]
+    commitment: Option<Commitment>,
+    /// Seconds to wait for each RPC request before giving up.
+    #[clap(global = true, long, default_value = "30")]
+    timeout: u64,
+    /// Seconds to wait for a sent transaction to be confirmed before checking whether it landed
+    /// and resending it. Defaults to waiting until its blockhash expires.
+    #[clap(global = true, long, value_name = "SECONDS")]
+    confirm_timeout: Option<u64>,
+    /// Only read accounts from an RPC node that has processed at least this slot, so reads right
+    /// after a transaction don't hit a lagging node behind a load balancer.
+    #[clap(global = true, long, value_name = "SLOT")]
+    min_context_slot: Option<Slot>,
+    /// Times to resend a transaction that fails to land before giving up.
+    #[clap(global = true, long, default_value = "10")]
+    max_retries: u32,
+    /// Most transaction resends across a whole run. Once they are used up, failing transactions
+    /// are no longer resent and the rest of a batch is not sent, as the endpoint is likely down.
+    #[clap(global = true, long, value_name = "N")]
+    max_total_retries: Option<u64>,
+    /// Milliseconds to wait between transaction submissions. The delay grows on its own while the
+    /// RPC endpoint rate limits submissions.
+    #[clap(global = true, long, value_name = "MS", default_value = "0")]
+    throttle: u64,
+    /// Named profile in ~/.config/squads-index/profiles.toml to fill in flags that aren't passed.
+    #[clap(global = true, long)]
+    profile: Option<String>,
+    /// Only scan transaction history older than this signature when searching for a multisig.
+    #[clap(global = true, long, value_name = "SIGNATURE")]
+    before: Option<Signature>,
+    /// Only scan transaction history from this signature or slot onwards when searching for a
+    /// multisig. A signature bound excludes the transaction itself.
+    #[clap(global = true, long, value_name = "SIGNATURE_OR_SLOT")]
+    since: Option<HistoryStart>,
+    /// Write run counters (transactions scanned, RPC calls, retries, matches, elapsed time) to
+    /// this file in the Prometheus text format when the command finishes.
+    #[clap(global = true, long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// Print how long account resolution, the history scan, transaction submission and
+    /// confirmation took to stderr when the command finishes.
+    #[clap(global = true, long)]
+    time: bool,
+    /// Print the number of requests sent to the RPC endpoint to stderr when the command
+    /// finishes, to gauge the cost of a command on a metered plan.
+    #[clap(global = true, long)]
+    report_rpc_usage: bool,
+    /// Output format for command results.
+    #[clap(global = true, short, long, value_enum, default_value = "text")]
+    output: OutputFormat,
+}
+
+/// Lower bound of the history scan, set by `--since`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum HistoryStart {
+    /// Passed to `getSignaturesForAddress` as the `until` cursor.
+    Signature(Signature),
+    /// Transactions in earlier slots are skipped.
+    Slot(Slot),
+}
+
+impl FromStr for HistoryStart {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(slot) = s.parse() {
+            return Ok(HistoryStart::Slot(slot));
+        }
+        Signature::from_str(s)
+            .map(HistoryStart::Signature)
+            .map_err(|_| format!("{} is neither a slot nor a transaction signature", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    /// Compact JSON, one object per line. `list` prints each index as soon as it is processed.
+    Jsonl,
+}
+
+impl OutputFormat {
+    /// Serializes a command result, pretty-printed for `json` and on a single line for `jsonl`.
+    fn to_json<T: Serialize>(self, value: &T) -> serde_json::Result<String> {
+        match self {
+            OutputFormat::Jsonl => serde_json::to_string(value),
+            _ => serde_json::to_string_pretty(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Commitment {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+impl From<Commitment> for CommitmentConfig {
+    fn from(commitment: Commitment) -> Self {
+        match commitment {
+            Commitment::Processed => CommitmentConfig::processed(),
+            Commitment::Confirmed => CommitmentConfig::confirmed(),
+            Commitment::Finalized => CommitmentConfig::finalized(),
+        }
+    }
+}
+
+/// Location of the profiles file read by `--profile`.
+const PROFILES_FILE: &str = "~/.config/squads-index/profiles.toml";
+
+/// Defaults for the global flags, loaded from a table in the profiles file, e.g.
+///
+/// ```toml
+/// [devnet]
+/// url = "dev"
+/// keypair_path = "~/.config/solana/devnet.json"
+/// program_id = "idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr"
+/// commitment = "finalized"
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    url: Option<String>,
+    keypair_path: Option<String>,
+    program_id: Option<String>,
+    squads_program_id: Option<String>,
+    commitment: Option<Commitment>,
+}
+
+/// Loads the profile called `name` from the profiles file.
+fn load_profile(name: &str) -> anyhow::Result<Profile> {
+    let path = shellexpand::tilde(PROFILES_FILE);
+    let contents = std::fs::read_to_string(&*path)
+        .map_err(|e| anyhow!("Failed to read profiles file {}: {}", path, e))?;
+    let mut profiles: HashMap<String, Profile> = toml::from_str(&contents)
+        .map_err(|e| anyhow!("Failed to parse profiles file {}: {}", path, e))?;
+    profiles
+        .remove(name)
+        .ok_or_else(|| anyhow!("Profile {} not found in {}", name, path))
 }
 
 #[derive(Parser, Debug)]
@@ -43,23 +257,283 @@ struct Args {
 enum Subcommand {
     /// Create an on-chain index that ties a multisig authority to the Squads V3 program
     Index {
-        /// Address of a Squads V3 Multisig account or an upgradeable program controlled by a Squads V3 Multisig
+        /// Addresses of Squads V3 Multisig accounts or upgradeable programs controlled by a Squads V3 Multisig
+        #[clap(required_unless_present_any = ["file", "all_vaults"])]
+        addresses: Vec<Pubkey>,
+        /// Read the addresses to index from a file with one address per line. Blank lines and lines starting with `#` are ignored. A `.json` file holds an array of `{"address", "authority_index"}` objects instead
+        #[clap(long, conflicts_with = "addresses")]
+        file: Option<PathBuf>,
+        /// Keypair path of a separate account that funds the index account rent. Defaults to the payer.
+        #[clap(long)]
+        rent_payer: Option<String>,
+        /// Index every authority (vault) of this Squads V3 Multisig that isn't indexed yet
+        #[clap(long, value_name = "MULTISIG", conflicts_with_all = ["addresses", "file"])]
+        all_vaults: Option<Pubkey>,
+        /// Number of authority indices, starting at 0, checked by --all-vaults
+        #[clap(long, default_value = "10", requires = "all_vaults", conflicts_with_all = ["addresses", "file"])]
+        vault_count: u32,
+        /// Squads V3 Multisig that controls the programs passed as addresses. Skips searching their transaction history for it, after checking that it derives each program's upgrade authority
+        #[clap(long, conflicts_with_all = ["file", "all_vaults"])]
+        multisig: Option<Pubkey>,
+        /// Index address the single address passed must derive. Nothing is sent if it derives another one, e.g. because --program-id is missing
+        #[clap(long, value_name = "INDEX", conflicts_with_all = ["file", "all_vaults"])]
+        expect_index_pda: Option<Pubkey>,
+        /// Maximum number of index transactions in flight at once
+        #[clap(long, default_value = "1")]
+        concurrency: NonZeroUsize,
+        /// After each index lands, wait up to this many seconds until the index account can be read at the configured commitment
+        #[clap(long, value_name = "SECONDS", conflicts_with = "encode_only")]
+        wait_visible: Option<u64>,
+        /// Print the index instruction for a Squads proposal instead of sending it. The Squads Vault pays the rent
+        #[clap(long, conflicts_with_all = ["all_vaults", "rent_payer"])]
+        encode_only: bool,
+        /// Sign the index transactions and write them to --out-file instead of sending them, to broadcast later with `submit`
+        #[clap(long, requires = "out_file", conflicts_with_all = ["all_vaults", "encode_only", "wait_visible"])]
+        sign_only: bool,
+        /// File that --sign-only writes the signed transactions to
+        #[clap(long, value_name = "PATH", requires = "sign_only")]
+        out_file: Option<PathBuf>,
+        /// Print the payer's balance before and after the run, and how much it spent
+        #[clap(long, conflicts_with_all = ["all_vaults", "encode_only", "sign_only"])]
+        payer_balance_after: bool,
+    },
+    /// Re-validate an existing index on-chain and record the slot it was last validated in
+    Reindex {
+        /// Address of a Squads V3 Multisig account, a multisig authority (Squads Vault), or an upgradeable program controlled by a Squads V3 Multisig
+        address: Pubkey,
+    },
+    /// Migrate a legacy empty index account to the current layout, recording its multisig on-chain
+    Migrate {
+        /// Address of a Squads V3 Multisig account, a multisig authority (Squads Vault), or an upgradeable program controlled by a Squads V3 Multisig
         address: Pubkey,
     },
     /// Check if an index exists for a given authority public key
     Check {
         /// Address of a multisig authority (Squads Vault) or an upgradeable program controlled by a Squads V3 Multisig
         address: Pubkey,
+        /// Keep polling until the authority is indexed, printing each status change
+        #[clap(long)]
+        watch: bool,
+        /// Polling interval in seconds for --watch (reconnect delay with --subscribe)
+        #[clap(long, default_value = "5", requires = "watch")]
+        interval: u64,
+        /// Watch via a WebSocket account subscription instead of polling
+        #[clap(long, requires = "watch")]
+        subscribe: bool,
+        /// Only print the members of the indexed multisig, one per line, or as a JSON array with --output json
+        #[clap(long, conflicts_with = "watch")]
+        members_only: bool,
+        /// Check every authority (vault) of the Squads V3 Multisig at ADDRESS instead of a single authority
+        #[clap(long, conflicts_with_all = ["watch", "members_only"])]
+        all: bool,
+        /// Number of authority indices, starting at 0, checked by --all. Defaults to every vault the multisig has created
+        #[clap(long, requires = "all")]
+        vault_count: Option<u32>,
+    },
+    /// Prepare the instruction that closes an index and refunds its rent, for the multisig to execute
+    Unindex {
+        /// Address of a Squads V3 Multisig account, a multisig authority (Squads Vault), or an upgradeable program controlled by a Squads V3 Multisig
+        address: Pubkey,
+        /// Account that receives the reclaimed rent. Defaults to the payer.
+        #[clap(long)]
+        refund: Option<Pubkey>,
+    },
+    /// Print the details of the multisig behind an authority, multisig, or program
+    Info {
+        /// Address of a multisig authority (Squads Vault), a Squads V3 Multisig account, or an upgradeable program controlled by a Squads V3 Multisig
+        address: Pubkey,
+    },
+    /// Print the raw bytes of an index account and the fields they decode to, for debugging its layout
+    Dump {
+        /// Address of a multisig authority (Squads Vault), or of an index account
+        address: Pubkey,
+        /// Treat the address as the index account itself even if the index program doesn't own it, e.g. a funded index that was never initialized
+        #[clap(long)]
+        index: bool,
+    },
+    /// Print the transaction that created an index account, for audit trails
+    History {
+        /// Address of a multisig authority (Squads Vault), or of an index account
+        address: Pubkey,
+        /// Treat the address as the index account itself, e.g. an index that has been closed
+        #[clap(long)]
+        index: bool,
+    },
+    /// List existing index accounts
+    List {
+        /// Only list indexes pointing at this Squads V3 Multisig
+        #[clap(long)]
+        multisig: Option<Pubkey>,
+    },
+    /// List index accounts that no longer match their multisig, as candidates for closing
+    ScanOrphans {
+        /// Also flag indexes whose authority no longer upgrades any program. Makes one getProgramAccounts call on the upgradeable loader per index
+        #[clap(long)]
+        programs: bool,
+        /// Print the instructions that close the orphans whose authority can still sign, for their multisigs to execute
+        #[clap(long)]
+        close: bool,
+        /// Account that receives the reclaimed rent with --close. Defaults to the payer.
+        #[clap(long, requires = "close")]
+        refund: Option<Pubkey>,
+    },
+    /// Print a JSON description of the index account layout, instruction format and PDA seeds
+    Schema,
+    /// Print the authority and index addresses derived from a multisig, without any RPC calls
+    Resolve {
+        /// Address of a Squads V3 Multisig account, or of an authority (Squads Vault) with --authority
+        address: Pubkey,
+        /// Authority index of the vault to derive
+        #[clap(long, default_value = "1", conflicts_with = "authority")]
+        authority_index: u32,
+        /// Treat the address as an authority and only derive its index address
+        #[clap(long)]
+        authority: bool,
+    },
+    /// Check the RPC endpoint, keypair and index program without changing any state
+    Doctor,
+    /// Broadcast index transactions signed earlier with `index --sign-only`
+    Submit {
+        /// File written by `index --sign-only`
+        path: PathBuf,
+    },
+    /// Print a JSON proof that a program is controlled by a Squads V3 Multisig through its index
+    Prove {
+        /// Address of an upgradeable program controlled by a Squads V3 Multisig
+        program: Pubkey,
+    },
+    /// Re-verify a proof written by `prove`, against the chain unless --offline
+    Verify {
+        /// File holding the JSON output of `prove`
+        path: PathBuf,
+        /// Only check the proof's derivations, without any RPC calls
+        #[clap(long)]
+        offline: bool,
     },
 }
 
+/// Public mainnet endpoint, used when neither a flag, a profile nor the Solana CLI config sets one.
+const MAINNET_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// Genesis hash of mainnet-beta, which identifies it behind any RPC URL.
+const MAINNET_GENESIS_HASH: &str = "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d";
+
 pub fn get_network(network_str: &str) -> &str {
     match network_str {
-        "mainnet" | "main" | "m" | "mainnet-beta" => "https://api.mainnet-beta.solana.com",
+        "mainnet" | "main" | "m" | "mainnet-beta" => MAINNET_URL,
+        "devnet" | "dev" | "d" => "https://api.devnet.solana.com",
+        "testnet" | "test" | "t" => "https://api.testnet.solana.com",
+        "localhost" | "local" | "l" => "http://localhost:8899",
         _ => network_str,
     }
 }
 
+/// Picks the RPC endpoint from `--url`, then the profile, then the Solana CLI config's
+/// `json_rpc_url`, and expands network aliases in whichever one wins.
+fn resolve_network_url(
+    url: Option<String>,
+    profile_url: Option<String>,
+    config: &Config,
+) -> String {
+    let url = url.or(profile_url).unwrap_or_else(|| {
+        if config.json_rpc_url.is_empty() {
+            MAINNET_URL.to_string()
+        } else {
+            config.json_rpc_url.clone()
+        }
+    });
+    get_network(&url).to_string()
+}
+
+/// Checks that the resolved endpoint is an http(s) URL with a host, so a typo in `--url` is
+/// reported up front instead of as a connection error in the middle of a command.
+fn validate_network_url(url: &str) -> anyhow::Result<()> {
+    let invalid = |reason: &str| {
+        anyhow!(
+            "Invalid RPC URL {}: {}. Pass an http:// or https:// URL, or one of main, dev, test, local",
+            url,
+            reason
+        )
+    };
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .ok_or_else(|| invalid("unsupported scheme"))?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(invalid("missing or malformed host"));
+    }
+    Ok(())
+}
+
+/// Seconds to wait for the `getHealth` probe sent before running a command.
+const RPC_PROBE_TIMEOUT_SECS: u64 = 5;
+
+/// Sends a `getHealth` request so an unreachable endpoint is reported before any work starts. A
+/// node that responds but reports itself unhealthy only produces a warning.
+async fn probe_rpc(client: &RpcClient, timeout: Duration) -> anyhow::Result<()> {
+    match tokio::time::timeout(timeout, client.get_health()).await {
+        Err(_) => Err(anyhow!("no response after {} seconds", timeout.as_secs())),
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => match e.kind() {
+            ClientErrorKind::Reqwest(_) | ClientErrorKind::Io(_) => Err(e.into()),
+            _ => {
+                warn!("RPC at {} reports it is unhealthy: {}", client.url(), e);
+                Ok(())
+            }
+        },
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cluster {
+    Mainnet,
+    Devnet,
+    Testnet,
+    Custom,
+}
+
+impl Cluster {
+    /// Guesses the cluster from an RPC endpoint URL, for explorer links and diagnostics. Private
+    /// endpoints often don't name their cluster, so this must not guard anything.
+    pub fn from_url(url: &str) -> Self {
+        if url.contains("mainnet") {
+            Cluster::Mainnet
+        } else if url.contains("devnet") {
+            Cluster::Devnet
+        } else if url.contains("testnet") {
+            Cluster::Testnet
+        } else {
+            Cluster::Custom
+        }
+    }
+}
+
+/// Asks the user to confirm a transaction. Mainnet spends real SOL and creates permanent state,
+/// so it requires typing `MAINNET` instead of a single letter. See [`Context::is_mainnet`].
+pub fn confirm_transaction(message: &str, is_mainnet: bool) -> anyhow::Result<bool> {
+    let confirmed = if is_mainnet {
+        prompt_for_typed_confirmation(message, "MAINNET")?
+    } else {
+        prompt_for_confirmation(message)?
+    };
+    // Ctrl-C at the prompt only takes effect once the line is read, and declines it.
+    Ok(confirmed && !interrupted())
+}
+
+pub fn prompt_for_typed_confirmation(message: &str, expected: &str) -> anyhow::Result<bool> {
+    write!(
+        std::io::stdout(),
+        "{}\nType {} to confirm: ",
+        message,
+        expected
+    )?;
+    std::io::stdout().flush()?;
+    let mut buffer = String::new();
+    std::io::stdin().read_line(&mut buffer)?;
+    Ok(buffer.trim() == expected)
+}
+
 pub fn prompt_for_confirmation(message: &str) -> anyhow::Result<bool> {
     loop {
         let input = get_response(message)?;
@@ -81,399 +555,6915 @@ pub fn get_response(message: &str) -> anyhow::Result<String> {
     write!(std::io::stdout(), "{}\n(y/n) ", message)?;
     std::io::stdout().flush()?;
     let mut buffer = String::new();
-    std::io::stdin().read_line(&mut buffer)?;
+    // Without this, a closed stdin would ask for yes or no forever.
+    if std::io::stdin().read_line(&mut buffer)? == 0 {
+        return Err(anyhow!("stdin closed before the prompt was answered"));
+    }
     Ok(buffer)
 }
 
+/// Why account data couldn't be read as a Squads V3 `Ms` account.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum MultisigDataError {
+    #[error("not a Squads V3 multisig account")]
+    NotMultisig,
+    #[error("malformed multisig account")]
+    Malformed,
+}
+
+/// Deserializes a Squads V3 `Ms` account. Data that is too short for the fields and members it
+/// declares is reported as malformed rather than read out of bounds.
+pub fn decode_multisig(data: &[u8]) -> Result<Ms, MultisigDataError> {
+    if data.len() < 8 || data[..8] != Ms::DISCRIMINATOR {
+        return Err(MultisigDataError::NotMultisig);
+    }
+    let vec_len = data
+        .get(MS_KEYS_LEN_OFFSET..MS_KEYS_OFFSET)
+        .and_then(|bytes| <[u8; 4]>::try_from(bytes).ok())
+        .map(u32::from_le_bytes)
+        .ok_or(MultisigDataError::Malformed)?;
+    // We need to pass in the exact offset of the vector's end to satisfy Borsh deserialization
+    let vec_end = (vec_len as usize)
+        .checked_mul(32)
+        .and_then(|len| len.checked_add(MS_KEYS_OFFSET))
+        .filter(|vec_end| *vec_end <= data.len())
+        .ok_or(MultisigDataError::Malformed)?;
+    Ms::try_from_slice(&data[8..vec_end]).map_err(|_| MultisigDataError::Malformed)
+}
+
+/// Deserializes a Squads V3 `Ms` account, returning `None` if it isn't one or is malformed.
+pub fn parse_multisig(data: &[u8]) -> Option<Ms> {
+    decode_multisig(data).ok()
+}
+
+/// Prints the multisig's threshold followed by a numbered table of its members.
+fn print_members(ms: &Ms) {
+    println!("Threshold: {} of {} members", ms.threshold, ms.keys.len());
+    let width = ms.keys.len().to_string().len().max(1);
+    println!("  {:>width$}  Member", "#", width = width);
+    for (i, key) in ms.keys.iter().enumerate() {
+        println!("  {:>width$}  {}", i + 1, key, width = width);
+    }
+}
+
+/// Set by `--no-color` or `NO_COLOR` to keep emoji and ANSI escapes out of the output.
+static PLAIN_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn plain_output() -> bool {
+    PLAIN_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Set by the first Ctrl-C. Scans, watches and batches check it between units of work and stop
+/// early instead of being killed mid-transaction.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes loops that are waiting when Ctrl-C is pressed.
+static INTERRUPT: Notify = Notify::const_new();
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Resolves once Ctrl-C has been pressed.
+async fn wait_for_interrupt() {
+    let notified = INTERRUPT.notified();
+    if interrupted() {
+        return;
+    }
+    notified.await;
+}
+
+/// Replaces the default Ctrl-C behavior. The first Ctrl-C asks the running command to stop after
+/// its current step, and a second one exits immediately.
+fn install_interrupt_handler() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(Status::Interrupted as i32);
+            }
+            warn!("Interrupted, stopping after the current step. Press Ctrl-C again to exit now");
+            INTERRUPT.notify_waiters();
+        }
+    });
+}
+
+/// The indicator printed after a check, degrading to plain text for `--no-color`.
+fn mark(ok: bool) -> &'static str {
+    match (plain_output(), ok) {
+        (false, true) => "✅",
+        (false, false) => "❌",
+        (true, true) => "OK",
+        (true, false) => "FAIL",
+    }
+}
+
+/// Routes diagnostics to stderr through `log`, leaving stdout for command results. `RUST_LOG`
+/// overrides the level picked by `--verbose`/`--quiet`.
+fn init_logger(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let write_style = if plain_output() {
+        WriteStyle::Never
+    } else {
+        WriteStyle::Auto
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .write_style(write_style)
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            Level::Info => writeln!(buf, "{}", record.args()),
+            level => writeln!(buf, "{}: {}", level.as_str().to_lowercase(), record.args()),
+        })
+        .init();
+}
+
+/// Keypair path that reads the keypair JSON from stdin.
+const STDIN_KEYPAIR_PATH: &str = "-";
+
+/// Loads a keypair from a file, from stdin with `-`, or from an environment variable with
+/// `env:VAR`. The latter two hold the same JSON byte array as a keypair file.
 pub fn get_payer_keypair_from_path(path: &str) -> anyhow::Result<Keypair> {
+    if path == STDIN_KEYPAIR_PATH {
+        let mut json = String::new();
+        std::io::stdin()
+            .read_to_string(&mut json)
+            .map_err(|e| anyhow!("Failed to read the keypair from stdin: {}", e))?;
+        return parse_keypair_json(&json, "stdin");
+    }
+    if let Some(var) = path.strip_prefix("env:") {
+        let json = std::env::var(var)
+            .map_err(|e| anyhow!("Failed to read the keypair from ${}: {}", var, e))?;
+        return parse_keypair_json(&json, &format!("${}", var));
+    }
     read_keypair_file(&*shellexpand::tilde(path)).map_err(|e| anyhow!(e.to_string()))
 }
 
+/// Lists the keypair paths of a `--keypair-pool`: the `.json` files of a directory in name
+/// order, or the entries of a comma separated list.
+fn keypair_pool_paths(pool: &str) -> anyhow::Result<Vec<String>> {
+    let dir = PathBuf::from(&*shellexpand::tilde(pool));
+    let paths = if dir.is_dir() {
+        let mut paths = std::fs::read_dir(&dir)
+            .map_err(|e| anyhow!("Failed to read {}: {}", dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    } else {
+        pool.split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    if paths.is_empty() {
+        return Err(anyhow!("Keypair pool {} has no keypairs", pool));
+    }
+    Ok(paths)
+}
+
+/// Loads the keypairs of a `--keypair-pool`. A keypair listed twice only takes one turn.
+fn load_keypair_pool(pool: &str) -> anyhow::Result<Vec<Keypair>> {
+    let mut keypairs: Vec<Keypair> = vec![];
+    for path in keypair_pool_paths(pool)? {
+        let keypair = get_payer_keypair_from_path(&path)
+            .map_err(|e| anyhow!("Failed to load pool keypair {}: {}", path, e))?;
+        if keypairs.iter().all(|k| k.pubkey() != keypair.pubkey()) {
+            keypairs.push(keypair);
+        }
+    }
+    Ok(keypairs)
+}
+
+/// Parses a keypair JSON byte array. Errors only name `source`: the parser's messages can quote
+/// the input, which is secret key material.
+fn parse_keypair_json(json: &str, source: &str) -> anyhow::Result<Keypair> {
+    serde_json::from_str::<Vec<u8>>(json)
+        .ok()
+        .and_then(|bytes| Keypair::from_bytes(&bytes).ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "{} does not hold a keypair: expected a JSON array of 64 bytes",
+                source
+            )
+        })
+}
+
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
+    let started = Instant::now();
     let cli = Args::parse();
+    // https://no-color.org: any non-empty value disables color.
+    let no_color = cli.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    PLAIN_OUTPUT.store(no_color, Ordering::Relaxed);
+    init_logger(cli.verbose, cli.quiet);
     let config = match CONFIG_FILE.as_ref() {
         Some(config_file) => Config::load(config_file).unwrap_or_else(|_| {
-            println!("Failed to load config file: {}", config_file);
+            warn!("Failed to load config file: {}", config_file);
             Config::default()
         }),
         None => Config::default(),
     };
-    let commitment = CommitmentConfig::confirmed();
-    let payer = get_payer_keypair_from_path(&cli.keypair_path.unwrap_or(config.keypair_path))
-        .expect("Keypair file does not exist. Please run `solana-keygen new`");
-    let network_url = &get_network(
-        &cli.url
-            .unwrap_or("https://api.mainnet-beta.solana.com".to_string()),
-    )
-    .to_string();
-    let client = RpcClient::new_with_commitment(network_url.to_string(), commitment);
-    match cli.subcommand {
-        Subcommand::Index { address } => {
-            index(&client, payer, cli.yes, address).await?;
+    // Explicit flags take precedence over the profile, which takes precedence over the Solana
+    // CLI config and built-in defaults.
+    let profile = match &cli.profile {
+        Some(name) => load_profile(name)?,
+        None => Profile::default(),
+    };
+    let commitment: CommitmentConfig = cli
+        .commitment
+        .or(profile.commitment)
+        .unwrap_or(Commitment::Confirmed)
+        .into();
+    let program_id = match (cli.program_id, profile.program_id) {
+        (Some(program_id), _) => program_id,
+        (None, Some(program_id)) => Pubkey::from_str(&program_id)
+            .map_err(|e| anyhow!("Invalid program_id {} in profile: {}", program_id, e))?,
+        (None, None) => squads_v3_index::id(),
+    };
+    let squads_program_id = match (cli.squads_program_id, profile.squads_program_id) {
+        (Some(squads_program_id), _) => squads_program_id,
+        (None, Some(squads_program_id)) => Pubkey::from_str(&squads_program_id).map_err(|e| {
+            anyhow!(
+                "Invalid squads_program_id {} in profile: {}",
+                squads_program_id,
+                e
+            )
+        })?,
+        (None, None) => squads_mpl::id(),
+    };
+    // The schema is static, so it doesn't need a keypair or an RPC connection.
+    if let Subcommand::Schema = cli.subcommand {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&schema(&program_id, &squads_program_id))?
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    // Deriving addresses is offline too.
+    if let Subcommand::Resolve {
+        address,
+        authority_index,
+        authority,
+    } = cli.subcommand
+    {
+        resolve(
+            &program_id,
+            &squads_program_id,
+            address,
+            authority_index,
+            authority,
+            cli.output,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Subcommand::Verify {
+        path,
+        offline: true,
+    } = &cli.subcommand
+    {
+        let proof = read_proof(path)?;
+        let checks = verify_proof_offline(&proof, &program_id, &squads_program_id);
+        return Ok(ExitCode::from(
+            print_doctor_report(&checks, cli.output)? as u8
+        ));
+    }
+    let keypair_path = cli
+        .keypair_path
+        .or(profile.keypair_path)
+        .unwrap_or_else(|| config.keypair_path.clone());
+    let network_url = &resolve_network_url(cli.url, profile.url, &config);
+    // The doctor reports a missing keypair or unreachable RPC instead of failing on it.
+    if let Subcommand::Doctor = cli.subcommand {
+        let checks = doctor(
+            network_url,
+            &keypair_path,
+            cli.fee_payer.as_deref(),
+            commitment,
+            Duration::from_secs(cli.timeout),
+            &program_id,
+        )
+        .await;
+        return Ok(ExitCode::from(
+            print_doctor_report(&checks, cli.output)? as u8
+        ));
+    }
+    // The confirmation prompt reads stdin too, so it can't share it with the keypair.
+    let reads_stdin =
+        keypair_path == STDIN_KEYPAIR_PATH || cli.fee_payer.as_deref() == Some(STDIN_KEYPAIR_PATH);
+    if reads_stdin && !cli.yes {
+        return Err(anyhow!(
+            "Reading a keypair from stdin requires --yes, since the confirmation prompt also reads stdin"
+        ));
+    }
+    let payer = match get_payer_keypair_from_path(&keypair_path) {
+        Ok(payer) => payer,
+        // Submitted transactions are already signed and proofs sign nothing, so the machine
+        // running them needs no keys. The stand-in keypair never signs anything.
+        Err(_)
+            if matches!(
+                cli.subcommand,
+                Subcommand::Submit { .. } | Subcommand::Prove { .. } | Subcommand::Verify { .. }
+            ) =>
+        {
+            Keypair::new()
         }
-        Subcommand::Check { address } => {
-            check(&client, address, true).await?;
+        Err(e) => {
+            return Err(anyhow!(
+                "Failed to load keypair {}: {}. Please run `solana-keygen new`",
+                keypair_path,
+                e
+            ))
         }
+    };
+    let fee_payer = cli
+        .fee_payer
+        .map(|path| get_payer_keypair_from_path(&path))
+        .transpose()?;
+    let fee_payer_pool = match &cli.keypair_pool {
+        Some(pool) => load_keypair_pool(pool)?,
+        None => vec![],
+    };
+    validate_network_url(network_url)?;
+    let ctx = Context {
+        client: RpcClient::new_with_timeout_and_commitment(
+            network_url.to_string(),
+            Duration::from_secs(cli.timeout),
+            commitment,
+        ),
+        network_url: network_url.to_string(),
+        payer,
+        history_before: cli.before,
+        history_since: cli.since,
+        fee_payer,
+        fee_payer_pool,
+        skip_confirmation: cli.yes,
+        skip_preflight: cli.skip_preflight,
+        max_retries: cli.max_retries,
+        max_total_retries: cli.max_total_retries,
+        total_retries: AtomicU64::new(0),
+        confirm_timeout: cli.confirm_timeout.map(Duration::from_secs),
+        throttle: Throttle::new(Duration::from_millis(cli.throttle)),
+        min_context_slot: AtomicU64::new(cli.min_context_slot.unwrap_or(0)),
+        output: cli.output,
+        program_id,
+        squads_program_id,
+        account_cache: Mutex::new(HashMap::new()),
+        blockhash: Mutex::new(None),
+        is_mainnet: Mutex::new(None),
+        metrics: Metrics::default(),
+        timings: Timings::default(),
+        time: cli.time,
+    };
+    let probe_timeout = Duration::from_secs(cli.timeout.min(RPC_PROBE_TIMEOUT_SECS));
+    if let Err(e) = probe_rpc(&ctx.client, probe_timeout).await {
+        error!("Could not reach RPC at {}: {}", ctx.network_url, e);
+        return Ok(ExitCode::from(Status::RpcError as u8));
     }
-
-    Ok(())
-}
-
-async fn index(
-    client: &RpcClient,
-    payer: Keypair,
-    skip_confirmation: bool,
-    address: Pubkey,
-) -> anyhow::Result<()> {
-    let mut is_program = false;
-    let account_data = client.get_account(&address).await;
-    let multisig = match account_data {
-        Ok(account_data) => {
-            if account_data.owner == squads_mpl::id() {
-                if account_data.data.len() < 8 {
-                    println!("Invalid multisig account {}", address);
-                    return Ok(());
-                }
-                let _ = Ms::try_from_slice(&account_data.data[8..])?;
-                let mut disc = [0_u8; 8];
-                disc.copy_from_slice(&account_data.data[..8]);
-                if Ms::DISCRIMINATOR != disc {
-                    println!("Invalid multisig account {}", address);
-                    return Ok(());
-                }
-                address
-            } else if account_data.owner == bpf_loader_upgradeable::id()
-                && account_data.data.len() == 36
-            {
-                let (program_data, _) = Pubkey::find_program_address(
-                    &[address.as_ref()],
-                    &bpf_loader_upgradeable::id(),
-                );
-                let program_data_account = client.get_account(&program_data).await?;
-                if program_data_account.data[12] == 0 {
-                    println!("Program is immutable");
-                    return Ok(());
-                }
-                let authority = Pubkey::try_from_slice(program_data_account.data[13..45].as_ref())?;
-                if authority.is_on_curve() {
-                    println!(
-                        "Ugrade Authority for {} is not a Program Derived Address ❌",
-                        address
-                    );
-                    return Ok(());
-                }
-                println!("Searching for multisig for {}", address);
-                let ms =
-                    get_multisig_account_from_key(client, &program_data, &authority, false).await;
-                if let Some(ms) = ms {
-                    is_program = true;
-                    println!("Found multisig for {}: {}", address, ms);
-                    ms
-                } else {
-                    println!("Failed to find multisig for {}", address);
-                    return Ok(());
-                }
-            } else {
-                println!("Invalid Account {}", address);
-                println!("{:#?}", account_data);
-                return Ok(());
-            }
-        }
-        Err(_) => {
-            println!("Account {} does not exist", address);
-            return Ok(());
+    install_interrupt_handler();
+    let result = run(&ctx, cli.subcommand).await;
+    if let Some(path) = &cli.metrics_file {
+        let metrics = ctx.metrics.render(
+            ctx.client.get_transport_stats().request_count as u64,
+            ctx.timings.elapsed(Phase::Scan),
+            started.elapsed(),
+        );
+        if let Err(e) = std::fs::write(path, metrics) {
+            warn!("Failed to write metrics to {}: {}", path.display(), e);
         }
+    }
+    if cli.time {
+        eprint!(
+            "{}",
+            ctx.timings.render(
+                ctx.metrics.transactions_scanned.load(Ordering::Relaxed),
+                ctx.client.get_transport_stats().request_count as u64,
+                started.elapsed(),
+            )
+        );
+    }
+    if cli.report_rpc_usage {
+        eprintln!(
+            "{}",
+            render_rpc_usage(
+                ctx.client.get_transport_stats().request_count as u64,
+                ctx.timings.scan_rpc_calls.load(Ordering::Relaxed),
+            )
+        );
+    }
+    let e = match result {
+        Ok(_) if interrupted() => return Ok(ExitCode::from(Status::Interrupted as u8)),
+        Ok(status) => return Ok(ExitCode::from(status as u8)),
+        Err(e) => e,
     };
+    let message = match &e {
+        // A hung endpoint surfaces as a timeout error instead of freezing the CLI.
+        CliError::Rpc(client_error) if is_timeout_error(client_error) => format!(
+            "Request to {} timed out after {} seconds",
+            ctx.network_url, cli.timeout
+        ),
+        e => e.to_string(),
+    };
+    match ctx.output {
+        OutputFormat::Text if matches!(e, CliError::Aborted) => info!("{}", message),
+        OutputFormat::Text => error!("{}", message),
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let report = ErrorReport {
+                error: e.kind(),
+                message,
+            };
+            println!("{}", ctx.output.to_json(&report)?);
+        }
+    }
+    Ok(ExitCode::from(e.exit_code()))
+}
 
-    let (authority_key, _) = Pubkey::find_program_address(
-        &[
-            b"squad",
-            multisig.as_ref(),
-            &1_u32.to_le_bytes(), // Authority index should just be 1
-            b"authority",
-        ],
-        &squads_mpl::id(),
-    );
+/// A single finding of `doctor` or `verify`.
+#[derive(Serialize)]
+struct DoctorCheck {
+    name: &'static str,
+    /// `false` when this would make other commands fail.
+    ok: bool,
+    detail: String,
+    /// Set when the check failed because the RPC endpoint couldn't be reached.
+    #[serde(skip)]
+    rpc_error: bool,
+}
 
-    let program_id = Pubkey::from_str("idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr")?;
+impl DoctorCheck {
+    fn new(name: &'static str, ok: bool, detail: String) -> Self {
+        Self {
+            name,
+            ok,
+            detail,
+            rpc_error: false,
+        }
+    }
 
-    let index_key = Pubkey::find_program_address(&[authority_key.as_ref()], &program_id).0;
+    fn rpc_error(name: &'static str, detail: String) -> Self {
+        Self {
+            name,
+            ok: false,
+            detail,
+            rpc_error: true,
+        }
+    }
+}
 
-    // Instruction to create the index account
-    let ix = Instruction {
-        program_id,
-        accounts: vec![
-            AccountMeta::new_readonly(system_program::id(), false),
-            AccountMeta::new_readonly(authority_key, false),
-            AccountMeta::new_readonly(multisig, false),
-            AccountMeta::new(payer.pubkey(), true),
-            AccountMeta::new(index_key, false),
-        ],
-        data: vec![],
+/// Checks everything a command needs before it can run: a valid and reachable RPC endpoint,
+/// readable keypairs with some SOL, and the index program deployed on the cluster. Only reads
+/// state, so it is safe to run against any cluster.
+async fn doctor(
+    network_url: &str,
+    keypair_path: &str,
+    fee_payer_path: Option<&str>,
+    commitment: CommitmentConfig,
+    timeout: Duration,
+    program_id: &Pubkey,
+) -> Vec<DoctorCheck> {
+    let mut checks = vec![DoctorCheck::new(
+        "Commitment",
+        true,
+        commitment_name(commitment),
+    )];
+    if let Err(e) = validate_network_url(network_url) {
+        checks.push(DoctorCheck::new("RPC URL", false, e.to_string()));
+        return checks;
+    }
+    checks.push(DoctorCheck::new(
+        "RPC URL",
+        true,
+        format!("{} ({:?})", network_url, Cluster::from_url(network_url)),
+    ));
+    let client =
+        RpcClient::new_with_timeout_and_commitment(network_url.to_string(), timeout, commitment);
+    let reachable = match probe_rpc(
+        &client,
+        timeout.min(Duration::from_secs(RPC_PROBE_TIMEOUT_SECS)),
+    )
+    .await
+    {
+        Ok(()) => {
+            let version = match client.get_version().await {
+                Ok(version) => format!("solana-core {}", version.solana_core),
+                Err(_) => "version unknown".to_string(),
+            };
+            checks.push(DoctorCheck::new(
+                "RPC",
+                true,
+                format!("reachable, {}", version),
+            ));
+            true
+        }
+        Err(e) => {
+            checks.push(DoctorCheck::rpc_error(
+                "RPC",
+                format!("could not reach {}: {}", network_url, e),
+            ));
+            false
+        }
     };
 
-    if skip_confirmation {
-        execute(ix, client, &payer).await?;
-    } else {
-        let Ok(ms_account) = client.get_account(&multisig).await else {
-            println!("Multisig account does not exist");
-            return Ok(());
+    let mut keypairs = vec![("Keypair", keypair_path)];
+    keypairs.extend(fee_payer_path.map(|path| ("Fee payer", path)));
+    for (name, path) in keypairs {
+        let keypair = match get_payer_keypair_from_path(path) {
+            Ok(keypair) => keypair,
+            Err(e) => {
+                checks.push(DoctorCheck::new(
+                    name,
+                    false,
+                    format!(
+                        "can't read {}: {}. Run `solana-keygen new` or pass the path",
+                        path, e
+                    ),
+                ));
+                continue;
+            }
         };
-        if check(client, authority_key, false).await? {
-            println!(
-                "{} already indexed!",
-                if is_program { address } else { authority_key }
-            );
-            return Ok(());
-        }
-        if ms_account.data.len() < 8 {
-            println!("Invalid multisig account {}", multisig);
-            return Ok(());
-        }
-        // We need to pass in the exact offset of the vector's end to satisfy Borsh deserialization
-        let vec_offset = 58;
-        let vec_len = u32::from_le_bytes(ms_account.data[54..58].try_into().unwrap());
-        let vec_end = (vec_offset + vec_len * 32) as usize;
-        let ms = Ms::try_from_slice(&ms_account.data[8..vec_end])?;
-        let mut disc = [0_u8; 8];
-        disc.copy_from_slice(&ms_account.data[..8]);
-        if Ms::DISCRIMINATOR != disc {
-            println!("Invalid multisig account {}", multisig);
-            return Ok(());
-        }
-        println!("{}/{} Multisig account exists", ms.threshold, ms.keys.len());
-        println!("Multisig key: {}", multisig);
-        println!("Authority key: {}", authority_key);
-        println!();
-        let confirmation_str = format!(
-            "Executing instruction: \n\n{:#?}\n\nCost: {} SOL\n",
-            ix, "0.00089588"
-        );
-        if prompt_for_confirmation(&confirmation_str)? {
-            execute(ix, client, &payer).await?;
-        } else {
-            println!("Exiting without executing instruction");
-            return Ok(());
+        if !reachable {
+            checks.push(DoctorCheck::new(
+                name,
+                true,
+                format!("{} ({})", keypair.pubkey(), path),
+            ));
+            continue;
         }
+        checks.push(match client.get_balance(&keypair.pubkey()).await {
+            Ok(balance) => DoctorCheck::new(
+                name,
+                balance > 0,
+                format!(
+                    "{} ({}) holds {} SOL",
+                    keypair.pubkey(),
+                    path,
+                    lamports_to_sol(balance)
+                ),
+            ),
+            Err(e) => DoctorCheck::rpc_error(
+                name,
+                format!("failed to fetch the balance of {}: {}", keypair.pubkey(), e),
+            ),
+        });
     }
-    if is_program {
-        println!("Program {} is now linked to Squads V3!", address);
-    } else {
-        println!("Authority {} is now indexed!", authority_key);
+
+    if reachable {
+        checks.push(
+            match client
+                .get_account_with_commitment(program_id, commitment)
+                .await
+            {
+                Ok(response) => match response.value {
+                    Some(account) if account.executable => DoctorCheck::new(
+                        "Index program",
+                        true,
+                        format!("{} is deployed", program_id),
+                    ),
+                    Some(_) => DoctorCheck::new(
+                        "Index program",
+                        false,
+                        format!("{} exists but is not an executable program", program_id),
+                    ),
+                    None => DoctorCheck::new(
+                        "Index program",
+                        false,
+                        format!(
+                            "{} is not deployed on this cluster. Check --url and --program-id",
+                            program_id
+                        ),
+                    ),
+                },
+                Err(e) => DoctorCheck::rpc_error(
+                    "Index program",
+                    format!("failed to fetch {}: {}", program_id, e),
+                ),
+            },
+        );
     }
-    Ok(())
+    checks
 }
 
-async fn check(client: &RpcClient, address: Pubkey, verbose: bool) -> anyhow::Result<bool> {
-    let mut is_program = false;
-    let authority = {
-        let account_res = client.get_account(&address).await;
-        match account_res {
-            Ok(a) => {
-                // Allow user to pass in a program ID
-                if a.owner == bpf_loader_upgradeable::id() && a.data.len() == 36 {
-                    let (program_data, _) = Pubkey::find_program_address(
-                        &[address.as_ref()],
-                        &bpf_loader_upgradeable::id(),
-                    );
-                    let program_data_account = client.get_account(&program_data).await?;
-                    if program_data_account.data[12] == 0 {
-                        println!("Program is immutable ✅");
-                        return Ok(true);
-                    }
-                    is_program = true;
-                    Pubkey::try_from_slice(program_data_account.data[13..45].as_ref())?
-                } else {
-                    address
-                }
+/// Prints the doctor's findings and returns the status they add up to: an RPC error if the
+/// endpoint couldn't be reached, invalid if anything else failed.
+fn print_doctor_report(checks: &[DoctorCheck], output: OutputFormat) -> anyhow::Result<Status> {
+    match output {
+        OutputFormat::Text => {
+            let width = checks
+                .iter()
+                .map(|check| check.name.len())
+                .max()
+                .unwrap_or(0);
+            for check in checks {
+                println!(
+                    "{} {:<width$}  {}",
+                    mark(check.ok),
+                    check.name,
+                    check.detail,
+                    width = width
+                );
             }
-            Err(_) => address,
         }
-    };
-    if authority.is_on_curve() {
-        if verbose {
-            println!(
-                "Authority {} is not a Program Derived Address ❌",
-                authority
-            );
+        OutputFormat::Json => println!("{}", output.to_json(&checks)?),
+        OutputFormat::Jsonl => {
+            for check in checks {
+                println!("{}", output.to_json(check)?);
+            }
         }
-        return Ok(false);
     }
+    Ok(if checks.iter().any(|check| check.rpc_error) {
+        Status::RpcError
+    } else if checks.iter().any(|check| !check.ok) {
+        Status::Invalid
+    } else {
+        Status::Success
+    })
+}
+
+/// Outcome of a subcommand, reported as the process exit code so scripts can branch on it.
+/// Any other error exits with 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    /// The command succeeded or, for `check`, the authority is indexed.
+    Success = 0,
+    /// `check` found no index for the authority.
+    NotIndexed = 2,
+    /// An address is invalid or can't be indexed.
+    Invalid = 3,
+    /// An RPC request failed.
+    RpcError = 4,
+    /// The confirmation prompt was declined.
+    Aborted = 5,
+    /// The command was stopped early with Ctrl-C.
+    Interrupted = 130,
+}
 
-    let program_id = Pubkey::from_str("idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr").unwrap();
-    let index_key = Pubkey::find_program_address(&[authority.as_ref()], &program_id).0;
+/// Why a subcommand stopped before finishing. `main` reports it as a message, or as a JSON
+/// object with `--output json`, and exits with [`CliError::exit_code`].
+#[derive(Debug, thiserror::Error)]
+enum CliError {
+    #[error("Failed to find multisig for {0}")]
+    MultisigNotFound(Pubkey),
+    #[error("Invalid multisig account {0}")]
+    InvalidMultisig(Pubkey),
+    #[error("Program is immutable")]
+    ImmutableProgram,
+    #[error("Index account does not exist for {0}")]
+    NotIndexed(Pubkey),
+    /// The arguments can't work, e.g. a refund to the index account being closed.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Exiting without executing instruction")]
+    Aborted,
+    /// Boxed, since a `ClientError` would make every `Result<_, CliError>` large.
+    #[error(transparent)]
+    Rpc(Box<ClientError>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
 
-    let Ok(index) = client.get_account(&index_key).await else {
-        if verbose {
-            println!("Index account does not exist for {} ❌", authority);
-        }
-        return Ok(false);
-    };
+impl From<ClientError> for CliError {
+    fn from(error: ClientError) -> Self {
+        CliError::Rpc(Box::new(error))
+    }
+}
 
-    if index.owner != program_id {
-        if verbose {
-            println!("Index account does not exist for {} ❌", authority);
+impl From<anyhow::Error> for CliError {
+    fn from(error: anyhow::Error) -> Self {
+        // Helpers that return `anyhow` errors still fail on RPC errors more often than not.
+        match error.downcast::<ClientError>() {
+            Ok(client_error) => CliError::Rpc(Box::new(client_error)),
+            Err(error) => CliError::Other(error),
         }
-        return Ok(false);
     }
-    if verbose {
-        println!("Index account exists for {} ✅", authority);
-        if is_program {
-            println!();
-            println!("{} is controlled by a Squads multisig", address);
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::MultisigNotFound(_)
+            | CliError::InvalidMultisig(_)
+            | CliError::ImmutableProgram
+            | CliError::Invalid(_) => Status::Invalid as u8,
+            CliError::NotIndexed(_) => Status::NotIndexed as u8,
+            CliError::Aborted => Status::Aborted as u8,
+            CliError::Rpc(_) => Status::RpcError as u8,
+            CliError::Io(_) | CliError::Json(_) | CliError::Other(_) => 1,
         }
-        println!();
-        if let Some(multisig_addr) =
-            get_multisig_account_from_key(client, &index_key, &authority, true).await
-        {
-            let account_data = client.get_account(&multisig_addr).await?;
-            // We need to pass in the exact offset of the vector's end to satisfy Borsh deserialization
-            let vec_offset = 58;
-            let vec_len = u32::from_le_bytes(account_data.data[54..58].try_into().unwrap());
-            let vec_end = (vec_offset + vec_len * 32) as usize;
-            if let Ok(multisig) = Ms::try_from_slice(&account_data.data[8..vec_end]) {
-                println!("Multisig details");
-                println!("Address: {}", multisig_addr);
-                println!("Threshold: {}/{}", multisig.threshold, multisig.keys.len());
-                println!("Members: {:#?}", multisig.keys);
-            }
+    }
+
+    /// Stable name of the error for JSON output.
+    fn kind(&self) -> &'static str {
+        match self {
+            CliError::MultisigNotFound(_) => "multisig_not_found",
+            CliError::InvalidMultisig(_) => "invalid_multisig",
+            CliError::ImmutableProgram => "immutable_program",
+            CliError::NotIndexed(_) => "not_indexed",
+            CliError::Invalid(_) => "invalid",
+            CliError::Aborted => "aborted",
+            CliError::Rpc(_) => "rpc_error",
+            CliError::Io(_) | CliError::Json(_) | CliError::Other(_) => "error",
         }
     }
-    Ok(true)
 }
 
-async fn get_multisig_account_from_key(
-    client: &RpcClient,
-    key: &Pubkey,
-    authority: &Pubkey,
-    reverse: bool,
-) -> Option<Pubkey> {
-    let mut transaction_history = client
-        .get_signatures_for_address(&key)
-        .await
-        .unwrap_or_default()
-        .iter()
-        .filter_map(|tx| {
-            if tx.err.is_none() {
-                Some(tx.clone())
+#[derive(Serialize)]
+struct ErrorReport {
+    error: &'static str,
+    message: String,
+}
+
+async fn run(ctx: &Context, subcommand: Subcommand) -> Result<Status, CliError> {
+    match subcommand {
+        Subcommand::Index {
+            addresses,
+            file,
+            rent_payer,
+            all_vaults,
+            vault_count,
+            multisig,
+            expect_index_pda,
+            concurrency,
+            wait_visible,
+            encode_only,
+            sign_only,
+            out_file,
+            payer_balance_after,
+        } => {
+            let wait_visible = wait_visible.map(Duration::from_secs);
+            let rent_payer = rent_payer
+                .map(|path| get_payer_keypair_from_path(&path))
+                .transpose()?;
+            if let Some(multisig) = all_vaults {
+                return index_all_vaults(
+                    ctx,
+                    rent_payer.as_ref(),
+                    multisig,
+                    vault_count,
+                    concurrency,
+                    wait_visible,
+                )
+                .await;
+            }
+            if expect_index_pda.is_some() && addresses.len() != 1 {
+                return Err(CliError::Invalid(
+                    "--expect-index-pda applies to a single address".to_string(),
+                ));
+            }
+            let targets = match file {
+                Some(file) => read_address_file(&file)?,
+                None => addresses
+                    .into_iter()
+                    .map(|address| IndexTarget {
+                        multisig,
+                        expected_index: expect_index_pda,
+                        ..IndexTarget::from(address)
+                    })
+                    .collect(),
+            };
+            if encode_only {
+                return encode_index(ctx, targets).await;
+            }
+            if let (true, Some(out_file)) = (sign_only, out_file) {
+                return sign_index(ctx, rent_payer.as_ref(), targets, &out_file).await;
+            }
+            index(
+                ctx,
+                rent_payer.as_ref(),
+                targets,
+                concurrency,
+                wait_visible,
+                payer_balance_after,
+            )
+            .await
+        }
+        Subcommand::Reindex { address } => reindex(ctx, address).await,
+        Subcommand::Migrate { address } => migrate(ctx, address).await,
+        Subcommand::Check {
+            address,
+            watch,
+            interval,
+            subscribe,
+            members_only,
+            all,
+            vault_count,
+        } => {
+            if all {
+                check_all_vaults(ctx, address, vault_count).await
+            } else if watch {
+                let websocket_url =
+                    subscribe.then(|| Config::compute_websocket_url(&ctx.network_url));
+                watch_index(ctx, address, interval, websocket_url.as_deref()).await
             } else {
-                None
+                check(ctx, address, members_only).await
             }
-        })
-        .collect::<Vec<_>>();
+        }
+        Subcommand::Unindex { address, refund } => unindex(ctx, address, refund).await,
+        Subcommand::Info { address } => info(ctx, address).await,
+        Subcommand::Dump { address, index } => dump(ctx, address, index).await,
+        Subcommand::History { address, index } => history(ctx, address, index).await,
+        Subcommand::List { multisig } => list(ctx, multisig).await,
+        Subcommand::ScanOrphans {
+            programs,
+            close,
+            refund,
+        } => scan_orphans(ctx, programs, close.then_some(refund)).await,
+        Subcommand::Schema => unreachable!("schema is printed before connecting"),
+        Subcommand::Resolve { .. } => unreachable!("addresses are resolved before connecting"),
+        Subcommand::Doctor => unreachable!("the doctor runs before loading the keypair"),
+        Subcommand::Submit { path } => submit(ctx, &path).await,
+        Subcommand::Prove { program } => prove(ctx, program).await,
+        Subcommand::Verify { path, .. } => verify(ctx, &path).await,
+    }
+}
 
-    let progress_bar = ProgressBar::new(42);
-    progress_bar
-        .set_style(ProgressStyle::default_spinner().template("{spinner:.green} {wide_msg}"));
-    progress_bar.enable_steady_tick(100);
+/// Number of transactions fetched concurrently while scanning history for the multisig.
+const SCAN_CONCURRENCY: usize = 4;
 
-    let total_transactions = transaction_history.len();
-    progress_bar.set_message(format!(
-        "[{}/{}] Searching transaction history",
-        0, total_transactions,
-    ));
+/// Attempts per transaction before a rate-limited or failed fetch is treated as a miss.
+const SCAN_MAX_RETRIES: u32 = 5;
 
-    if reverse {
-        transaction_history.reverse();
-    }
+/// Delay before the first retry of a transaction fetch, doubled on each attempt.
+const SCAN_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
 
-    for (i, tx) in transaction_history.iter().enumerate() {
-        let sig = &Signature::from_str(&tx.signature).unwrap();
-        progress_bar.set_message(format!(
-            "[{}/{}] Searching transaction history: {}",
-            i + 1,
-            total_transactions,
-            sig,
-        ));
-        if let Some(key) = extract_multisig_key_from_transaction(client, &sig, authority).await {
-            progress_bar.set_message(format!("Found multisig key after {} transactions", i + 1));
-            return Some(key);
+/// Attempts at an RPC read that keeps failing in transit before the error is returned.
+const RPC_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before retrying a failed RPC read, doubled on each attempt.
+const RPC_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Most transactions checked in a single history scan before giving up.
+const SCAN_MAX_TRANSACTIONS: usize = 250;
+
+/// Most signatures fetched for an index account by `history` before giving up.
+const HISTORY_MAX_SIGNATURES: usize = 10_000;
+
+/// Authority indices, starting at 0, that the history scan also derives to explain an authority
+/// that isn't vault 1 of any multisig.
+const SCAN_VAULT_COUNT: u32 = 10;
+
+/// Connection, payer, program and flags shared by the subcommands.
+struct Context {
+    client: RpcClient,
+    network_url: String,
+    payer: Keypair,
+    /// Bounds of the history scan, set by `--before` and `--since`.
+    history_before: Option<Signature>,
+    history_since: Option<HistoryStart>,
+    /// Pays transaction fees in place of `payer`, set by `--fee-payer`.
+    fee_payer: Option<Keypair>,
+    /// Take turns paying the fees of batch transactions, set by `--keypair-pool`.
+    fee_payer_pool: Vec<Keypair>,
+    skip_confirmation: bool,
+    skip_preflight: bool,
+    max_retries: u32,
+    /// Budget of resends shared by all transactions of the run, set by `--max-total-retries`.
+    max_total_retries: Option<u64>,
+    /// Resends spent so far, counted against `max_total_retries`.
+    total_retries: AtomicU64,
+    /// Longest wait for a sent transaction to be confirmed, set by `--confirm-timeout`.
+    confirm_timeout: Option<Duration>,
+    throttle: Throttle,
+    /// Oldest slot an account read may reflect, set by `--min-context-slot` and raised to the slot
+    /// of a transaction before its index is read back. 0 allows any slot.
+    min_context_slot: AtomicU64,
+    output: OutputFormat,
+    program_id: Pubkey,
+    /// The Squads V3 program that owns multisigs, set by `--squads-program-id`.
+    squads_program_id: Pubkey,
+    /// Accounts fetched during this run, `None` for accounts that don't exist.
+    account_cache: Mutex<HashMap<Pubkey, Option<Account>>>,
+    /// Blockhash shared by the transactions sent during this run.
+    blockhash: Mutex<Option<Hash>>,
+    /// Whether the endpoint serves mainnet-beta, once its genesis hash has been fetched.
+    is_mainnet: Mutex<Option<bool>>,
+    metrics: Metrics,
+    timings: Timings,
+    /// Set by `--time`. Transactions are then confirmed without the spinner, so that sending and
+    /// confirming can be timed separately.
+    time: bool,
+}
+
+/// Counters written to `--metrics-file` at the end of a run.
+#[derive(Default)]
+struct Metrics {
+    /// Transactions fetched and inspected while searching history for a multisig.
+    transactions_scanned: AtomicU64,
+    /// History searches that found the multisig.
+    matches_found: AtomicU64,
+    /// Resent transactions and refetched history entries.
+    retries: AtomicU64,
+}
+
+impl Metrics {
+    /// Renders the counters in the Prometheus text exposition format, so the file can be read by
+    /// the node exporter's textfile collector or parsed line by line.
+    fn render(&self, rpc_calls: u64, scan: Duration, elapsed: Duration) -> String {
+        let metrics = [
+            (
+                "transactions_scanned",
+                "Transactions inspected while searching history for a multisig.",
+                "counter",
+                self.transactions_scanned
+                    .load(Ordering::Relaxed)
+                    .to_string(),
+            ),
+            (
+                "matches_found",
+                "History searches that found the multisig.",
+                "counter",
+                self.matches_found.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "scan_seconds",
+                "Wall clock time spent searching history for a multisig.",
+                "counter",
+                format!("{:.3}", scan.as_secs_f64()),
+            ),
+            (
+                "retries",
+                "Resent transactions and refetched history entries.",
+                "counter",
+                self.retries.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "rpc_calls",
+                "HTTP requests sent to the RPC endpoint.",
+                "counter",
+                rpc_calls.to_string(),
+            ),
+            (
+                "elapsed_seconds",
+                "Wall clock time of the run.",
+                "gauge",
+                format!("{:.3}", elapsed.as_secs_f64()),
+            ),
+        ];
+        let mut out = String::new();
+        for (name, help, kind, value) in metrics {
+            out.push_str(&format!(
+                "# HELP squads_index_{name} {help}\n# TYPE squads_index_{name} {kind}\nsquads_index_{name} {value}\n"
+            ));
         }
+        out
     }
-    None
 }
 
-async fn extract_multisig_key_from_transaction(
-    client: &RpcClient,
-    signature: &Signature,
-    authority: &Pubkey,
-) -> Option<Pubkey> {
-    let transaction_details = client
-        .get_transaction_with_config(
-            &signature,
-            RpcTransactionConfig {
-                commitment: Some(CommitmentConfig::confirmed()),
-                max_supported_transaction_version: Some(1),
-                encoding: Some(UiTransactionEncoding::Binary),
-            },
-        )
-        .await
-        .ok()?;
-    let tx = transaction_details
-        .transaction
-        .transaction
-        .decode()?
-        .into_legacy_transaction()?;
-    for account in tx.message.account_keys.iter() {
-        let (derived_authority_key, _) = Pubkey::find_program_address(
-            &[
-                b"squad",
-                account.as_ref(),
-                &1_u32.to_le_bytes(), // Authority index should just be 1
-                b"authority",
-            ],
-            &squads_mpl::id(),
+/// Smallest delay between submissions once the endpoint has rate limited one.
+const THROTTLE_MIN_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Largest delay between submissions that rate limits can push the throttle to.
+const THROTTLE_MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Spaces out transaction submissions. Each rate limited submission doubles the delay, and each
+/// successful one halves it again, down to the `--throttle` delay.
+struct Throttle {
+    base: Duration,
+    delay_ms: AtomicU64,
+    /// Earliest time the next transaction may be sent.
+    next_send: tokio::sync::Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(base: Duration) -> Self {
+        Throttle {
+            base,
+            delay_ms: AtomicU64::new(base.as_millis() as u64),
+            next_send: tokio::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms.load(Ordering::Relaxed))
+    }
+
+    /// Waits until the next submission is due and reserves the following slot. Concurrent callers
+    /// take turns.
+    async fn wait(&self) {
+        let mut next_send = self.next_send.lock().await;
+        tokio::time::sleep_until((*next_send).into()).await;
+        *next_send = Instant::now() + self.delay();
+    }
+
+    fn rate_limited(&self) {
+        let delay =
+            (self.delay() * 2).clamp(THROTTLE_MIN_BACKOFF, THROTTLE_MAX_BACKOFF.max(self.base));
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+        warn!(
+            "Rate limited by the RPC endpoint, waiting {:?} between transactions",
+            delay
         );
-        if &derived_authority_key != authority {
-            continue;
+    }
+
+    fn succeeded(&self) {
+        let delay = (self.delay() / 2).max(self.base);
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// A part of a command timed with `--time`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+    /// Reading the accounts that addresses resolve to.
+    Resolution,
+    /// Searching transaction history for a multisig.
+    Scan,
+    /// Sending transactions.
+    Submission,
+    /// Waiting for sent transactions to be confirmed.
+    Confirmation,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [
+        Phase::Resolution,
+        Phase::Scan,
+        Phase::Submission,
+        Phase::Confirmation,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::Resolution => "Account resolution",
+            Phase::Scan => "History scan",
+            Phase::Submission => "Submission",
+            Phase::Confirmation => "Confirmation",
         }
-        return Some(account.clone());
     }
-    None
 }
 
-async fn execute(ix: Instruction, client: &RpcClient, payer: &Keypair) -> anyhow::Result<()> {
-    let authority_key = ix.accounts[1].pubkey.clone();
-    let multisig_key = ix.accounts[2].pubkey.clone();
-    let blockhash = client.get_latest_blockhash().await?;
-    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &[payer], blockhash);
-    let mut retries = 1;
-    loop {
-        match client
-            .send_and_confirm_transaction_with_spinner_and_commitment(
-                &tx,
-                CommitmentConfig::confirmed(),
-            )
-            .await
-        {
-            Ok(_) => {
-                break;
+/// Time spent in each [`Phase`], printed with `--time`. Phases of concurrent tasks are summed, so
+/// they can add up to more than the wall clock time of the run.
+#[derive(Default)]
+struct Timings {
+    nanos: [AtomicU64; Phase::ALL.len()],
+    /// RPC requests sent while searching transaction history.
+    scan_rpc_calls: AtomicU64,
+}
+
+impl Timings {
+    /// Starts timing `phase` until the returned guard is dropped.
+    fn start(&self, phase: Phase) -> PhaseTimer<'_> {
+        PhaseTimer {
+            timings: self,
+            phase,
+            started: Instant::now(),
+        }
+    }
+
+    fn elapsed(&self, phase: Phase) -> Duration {
+        Duration::from_nanos(self.nanos[phase as usize].load(Ordering::Relaxed))
+    }
+
+    fn render(&self, transactions_scanned: u64, rpc_calls: u64, elapsed: Duration) -> String {
+        let mut out = String::from("Timing:\n");
+        for phase in Phase::ALL {
+            let time = self.elapsed(phase);
+            out.push_str(&format!(
+                "  {:<20} {:>9.3}s",
+                phase.label(),
+                time.as_secs_f64()
+            ));
+            if phase == Phase::Scan && transactions_scanned > 0 {
+                out.push_str(&format!(
+                    " ({} transactions, {:.1} tx/s, {} RPC calls)",
+                    transactions_scanned,
+                    transactions_scanned as f64 / time.as_secs_f64().max(0.001),
+                    self.scan_rpc_calls.load(Ordering::Relaxed)
+                ));
             }
-            Err(e) => {
-                match e.kind() {
-                    ClientErrorKind::RpcError(RpcError::RpcResponseError {
-                        code: _,
-                        message: _,
-                        data,
-                    }) => {
-                        if let RpcResponseErrorData::SendTransactionPreflightFailure(_) = data {
-                            println!("Invalid multisig account {}", multisig_key);
-                            return Ok(());
-                        }
-                    }
-                    _ => {}
+            out.push('\n');
+        }
+        out.push_str(&format!(
+            "  {:<20} {:>9.3}s ({} RPC calls)\n",
+            "Total",
+            elapsed.as_secs_f64(),
+            rpc_calls
+        ));
+        out
+    }
+}
+
+/// Summarizes the RPC requests of a run for `--report-rpc-usage`. WebSocket subscriptions are not
+/// requests and aren't counted.
+fn render_rpc_usage(rpc_calls: u64, scan_rpc_calls: u64) -> String {
+    let mut out = format!("RPC requests: {}", rpc_calls);
+    if scan_rpc_calls > 0 {
+        out.push_str(&format!(" ({} scanning history)", scan_rpc_calls));
+    }
+    out
+}
+
+/// Adds the time since it was created to its phase when dropped.
+struct PhaseTimer<'a> {
+    timings: &'a Timings,
+    phase: Phase,
+    started: Instant,
+}
+
+impl Drop for PhaseTimer<'_> {
+    fn drop(&mut self) {
+        self.timings.nanos[self.phase as usize]
+            .fetch_add(self.started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Context {
+    /// Derives the Squads authority (vault) of `multisig` at `authority_index`.
+    fn squads_authority(&self, multisig: &Pubkey, authority_index: u32) -> (Pubkey, u8) {
+        derive_squads_authority_with_program_id(multisig, authority_index, &self.squads_program_id)
+    }
+
+    /// The account that pays transaction fees and signs first.
+    fn fee_payer(&self) -> &Keypair {
+        self.fee_payer_for(0)
+    }
+
+    /// The fee payer of the transaction at `position` in a batch, rotating through the
+    /// `--keypair-pool` if there is one.
+    fn fee_payer_for(&self, position: usize) -> &Keypair {
+        match self.fee_payer_pool.len() {
+            0 => self.fee_payer.as_ref().unwrap_or(&self.payer),
+            len => &self.fee_payer_pool[position % len],
+        }
+    }
+
+    /// Returns the blockhash shared by this run's transactions, fetching it on first use.
+    async fn latest_blockhash(&self) -> Result<Hash, ClientError> {
+        if let Some(blockhash) = *self.blockhash.lock().unwrap() {
+            return Ok(blockhash);
+        }
+        let blockhash = self.client.get_latest_blockhash().await?;
+        *self.blockhash.lock().unwrap() = Some(blockhash);
+        Ok(blockhash)
+    }
+
+    /// Whether the endpoint serves mainnet-beta, told by its genesis hash rather than its URL so
+    /// private and paid mainnet endpoints are recognized too. Fetched on first use.
+    async fn is_mainnet(&self) -> Result<bool, ClientError> {
+        if let Some(is_mainnet) = *self.is_mainnet.lock().unwrap() {
+            return Ok(is_mainnet);
+        }
+        let genesis_hash = self
+            .with_retries("fetching the genesis hash", || {
+                self.client.get_genesis_hash()
+            })
+            .await?;
+        let is_mainnet = genesis_hash.to_string() == MAINNET_GENESIS_HASH;
+        *self.is_mainnet.lock().unwrap() = Some(is_mainnet);
+        Ok(is_mainnet)
+    }
+
+    /// Replaces the shared blockhash once `expired` is no longer valid. When several in-flight
+    /// transactions hit the same expiry, only the first one fetches a new blockhash.
+    async fn refresh_blockhash(&self, expired: &Hash) -> Result<Hash, ClientError> {
+        if let Some(blockhash) = *self.blockhash.lock().unwrap() {
+            if blockhash != *expired {
+                return Ok(blockhash);
+            }
+        }
+        let blockhash = self.client.get_new_latest_blockhash(expired).await?;
+        *self.blockhash.lock().unwrap() = Some(blockhash);
+        Ok(blockhash)
+    }
+
+    /// Fetches `key`, reusing the account if it was already fetched during this run so every
+    /// command sees one consistent view of each account.
+    /// A missing account is `Ok(None)`. Requests that fail in transit are retried, so an error
+    /// means the endpoint kept failing, not that the account doesn't exist.
+    async fn get_account(&self, key: &Pubkey) -> Result<Option<Account>, ClientError> {
+        if let Some(account) = self.account_cache.lock().unwrap().get(key) {
+            return Ok(account.clone());
+        }
+        let account = self.fetch_account(key).await?;
+        self.account_cache
+            .lock()
+            .unwrap()
+            .insert(*key, account.clone());
+        Ok(account)
+    }
+
+    /// Fetches `key` from the RPC, bypassing the cache.
+    async fn fetch_account(&self, key: &Pubkey) -> Result<Option<Account>, ClientError> {
+        self.with_retries(&format!("account {}", key), || async {
+            Ok(self
+                .client
+                .get_account_with_config(key, self.account_config())
+                .await?
+                .value)
+        })
+        .await
+    }
+
+    /// Takes one resend from the `--max-total-retries` budget. Returns false once it is used up.
+    fn spend_retry(&self) -> bool {
+        let spent = self.total_retries.fetch_add(1, Ordering::Relaxed) + 1;
+        match self.max_total_retries {
+            Some(max_total_retries) => spent <= max_total_retries,
+            None => true,
+        }
+    }
+
+    /// Whether a transaction was denied a resend because the `--max-total-retries` budget ran out.
+    fn retry_budget_exhausted(&self) -> bool {
+        self.max_total_retries.is_some_and(|max_total_retries| {
+            self.total_retries.load(Ordering::Relaxed) > max_total_retries
+        })
+    }
+
+    /// Requires later account reads to reflect at least `slot`, so they see what landed by then.
+    fn raise_min_context_slot(&self, slot: Slot) {
+        self.min_context_slot.fetch_max(slot, Ordering::Relaxed);
+    }
+
+    fn account_config(&self) -> RpcAccountInfoConfig {
+        RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64Zstd),
+            commitment: Some(self.client.commitment()),
+            min_context_slot: match self.min_context_slot.load(Ordering::Relaxed) {
+                0 => None,
+                slot => Some(slot),
+            },
+            data_slice: None,
+        }
+    }
+
+    /// Runs an RPC read, backing off and retrying while it fails in transit or is rate limited.
+    /// Other errors, and the transient one once the attempts run out, are returned as is.
+    async fn with_retries<T, F, Fut>(&self, what: &str, request: F) -> Result<T, ClientError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let _timer = self.timings.start(Phase::Resolution);
+        let mut backoff = RPC_INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            match request().await {
+                Err(e) if attempt < RPC_MAX_ATTEMPTS && is_transient_rpc_error(&e) => {
+                    debug!("Retrying {} in {:?}: {}", what, backoff, e);
+                    self.metrics.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
                 }
-                println!("Attempt {}. Error creating index account: {}", retries, e);
-                retries += 1;
-                if retries > 10 {
-                    println!("Failed to create index account after 10 attempts");
-                    return Ok(());
+                result => return result,
+            }
+        }
+    }
+
+    /// Like [`Context::get_account`] for many accounts at once. Only the accounts that aren't
+    /// cached yet are requested, in bulk.
+    async fn get_multiple_accounts(
+        &self,
+        keys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, ClientError> {
+        let missing = {
+            let cache = self.account_cache.lock().unwrap();
+            keys.iter()
+                .filter(|key| !cache.contains_key(key))
+                .copied()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+        let fetched = self.get_multiple_accounts_chunked(&missing).await?;
+        let mut cache = self.account_cache.lock().unwrap();
+        cache.extend(missing.into_iter().zip(fetched));
+        Ok(keys
+            .iter()
+            .map(|key| cache.get(key).cloned().flatten())
+            .collect())
+    }
+
+    /// Fetches `keys` with `getMultipleAccounts` in chunks of up to 100, returning the accounts in
+    /// the same order. A chunk that still fails after retrying is fetched one account at a time
+    /// instead, so a single bad request doesn't fail the whole batch.
+    async fn get_multiple_accounts_chunked(
+        &self,
+        keys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, ClientError> {
+        let mut accounts = Vec::with_capacity(keys.len());
+        for chunk in keys.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let bulk = self
+                .with_retries(&format!("{} accounts", chunk.len()), || async {
+                    Ok(self
+                        .client
+                        .get_multiple_accounts_with_config(chunk, self.account_config())
+                        .await?
+                        .value)
+                })
+                .await;
+            match bulk {
+                Ok(chunk_accounts) => accounts.extend(chunk_accounts),
+                Err(e) => {
+                    warn!(
+                        "Failed to fetch {} accounts in bulk, fetching them individually: {}",
+                        chunk.len(),
+                        e
+                    );
+                    for key in chunk {
+                        accounts.push(self.fetch_account(key).await?);
+                    }
                 }
-                continue;
             }
         }
+        Ok(accounts)
+    }
+}
+
+/// Vault of a multisig that `index` links unless a batch file entry picks another one.
+const DEFAULT_AUTHORITY_INDEX: u32 = 1;
+
+/// An address to index, with the vault to link if it is a multisig.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct IndexTarget {
+    address: Pubkey,
+    authority_index: u32,
+    /// The multisig of a program, set by `--multisig` to skip the history scan.
+    multisig: Option<Pubkey>,
+    /// The index address the target must derive, set by `--expect-index-pda`.
+    expected_index: Option<Pubkey>,
+}
+
+impl From<Pubkey> for IndexTarget {
+    fn from(address: Pubkey) -> Self {
+        Self {
+            address,
+            authority_index: DEFAULT_AUTHORITY_INDEX,
+            multisig: None,
+            expected_index: None,
+        }
+    }
+}
+
+/// An entry of a JSON batch file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchFileEntry {
+    address: String,
+    #[serde(default = "default_authority_index")]
+    authority_index: u32,
+}
+
+fn default_authority_index() -> u32 {
+    DEFAULT_AUTHORITY_INDEX
+}
+
+/// A resolved address that is ready to be indexed.
+struct IndexEntry {
+    address: Pubkey,
+    multisig: Pubkey,
+    authority_key: Pubkey,
+    authority_index: u32,
+    index_key: Pubkey,
+    is_program: bool,
+    ix: Instruction,
+}
+
+#[derive(Serialize)]
+struct IndexResult {
+    address: String,
+    authority: String,
+    authority_index: u32,
+    multisig: String,
+    index: String,
+    signature: String,
+    explorer_url: String,
+    /// Whether the index account could be read back after landing, set by `--wait-visible`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    visible: Option<bool>,
+    /// The pool keypair that paid the fee, set by `--keypair-pool`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee_payer: Option<String>,
+    /// Whether `address` is a program rather than a multisig, which only changes the wording.
+    #[serde(skip)]
+    is_program: bool,
+}
+
+impl IndexResult {
+    fn print(&self) {
+        println!("Successfully created index for {}", self.authority);
+        println!("Signature: {}", self.signature);
+        println!("Explorer: {}", self.explorer_url);
+        if let Some(fee_payer) = &self.fee_payer {
+            println!("Fee paid by: {}", fee_payer);
+        }
+        if self.is_program {
+            println!("Program {} is now linked to Squads V3!", self.address);
+        } else {
+            println!("Authority {} is now indexed!", self.authority);
+        }
+    }
+}
+
+/// Estimated lamports spent by an index run, covering only the entries that send a transaction.
+#[derive(Serialize)]
+struct IndexCost {
+    entries: usize,
+    rent: u64,
+    fees: u64,
+    total: u64,
+}
+
+/// Rollup of an index run, so a large batch can be confirmed at a glance.
+#[derive(Default, Serialize)]
+struct IndexSummary {
+    created: usize,
+    already_indexed: usize,
+    /// Addresses that couldn't be resolved to an indexable authority.
+    invalid: usize,
+    /// Entries whose transaction was rejected or never landed.
+    failed: usize,
+    /// Entries that were never sent because the run was interrupted or ran out of retries.
+    not_sent: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    not_sent_addresses: Vec<String>,
+    signatures: Vec<String>,
+    /// Entries created by each `--keypair-pool` fee payer.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fee_payers: BTreeMap<String, usize>,
+}
+
+impl IndexSummary {
+    fn print(&self) {
+        println!("Summary");
+        for (label, count) in [
+            ("Created", self.created),
+            ("Already indexed", self.already_indexed),
+            ("Invalid", self.invalid),
+            ("Failed", self.failed),
+        ] {
+            println!("  {:<15}  {}", label, count);
+        }
+        if self.not_sent > 0 {
+            println!("  {:<15}  {}", "Not sent", self.not_sent);
+        }
+        if !self.not_sent_addresses.is_empty() {
+            println!("Not sent:");
+            for address in self.not_sent_addresses.iter() {
+                println!("  {}", address);
+            }
+        }
+        if !self.fee_payers.is_empty() {
+            println!("Fees paid by:");
+            for (fee_payer, count) in self.fee_payers.iter() {
+                println!("  {}  {}", fee_payer, count);
+            }
+        }
+        if !self.signatures.is_empty() {
+            println!("Signatures:");
+            for signature in self.signatures.iter() {
+                println!("  {}", signature);
+            }
+        }
+    }
+}
+
+/// Balance of the payer around an index run, reported with `--payer-balance-after` to check the
+/// estimated cost against what was actually spent.
+#[derive(Serialize)]
+struct PayerBalance {
+    payer: String,
+    balance_before: u64,
+    balance_after: u64,
+    /// Negative if the payer received more than it spent during the run.
+    spent: i64,
+}
+
+impl PayerBalance {
+    fn new(payer: &Pubkey, balance_before: u64, balance_after: u64) -> Self {
+        Self {
+            payer: payer.to_string(),
+            balance_before,
+            balance_after,
+            spent: balance_before as i64 - balance_after as i64,
+        }
+    }
+
+    fn print(&self, cost: Option<&IndexCost>) {
+        println!("Payer {}", self.payer);
+        println!(
+            "  {:<15}  {} SOL",
+            "Before",
+            lamports_to_sol(self.balance_before)
+        );
+        println!(
+            "  {:<15}  {} SOL",
+            "After",
+            lamports_to_sol(self.balance_after)
+        );
+        let mut spent = format!(
+            "{}{} SOL",
+            if self.spent < 0 { "-" } else { "" },
+            lamports_to_sol(self.spent.unsigned_abs())
+        );
+        if let Some(cost) = cost {
+            spent.push_str(&format!(" (estimated {} SOL)", lamports_to_sol(cost.total)));
+        }
+        println!("  {:<15}  {}", "Spent", spent);
+    }
+}
+
+#[derive(Serialize)]
+struct IndexReport {
+    /// `None` when every entry was already indexed and nothing was sent.
+    cost: Option<IndexCost>,
+    results: Vec<IndexResult>,
+    /// Addresses that were skipped because they can't be indexed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ineligible: Vec<IneligibleAddress>,
+    summary: IndexSummary,
+    /// Set by `--payer-balance-after`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payer_balance: Option<PayerBalance>,
+}
+
+async fn index(
+    ctx: &Context,
+    rent_payer: Option<&Keypair>,
+    targets: Vec<IndexTarget>,
+    concurrency: NonZeroUsize,
+    wait_visible: Option<Duration>,
+    payer_balance_after: bool,
+) -> Result<Status, CliError> {
+    let is_batch = targets.len() > 1;
+    let payer = ctx.payer.pubkey();
+    let balance_before = match payer_balance_after {
+        true => Some(ctx.client.get_balance(&payer).await?),
+        false => None,
+    };
+    let (status, report) =
+        index_addresses(ctx, rent_payer, targets, concurrency, wait_visible).await?;
+    if status == Status::Aborted {
+        return Err(CliError::Aborted);
+    }
+    let Some(mut report) = report else {
+        return Ok(status);
+    };
+    if let Some(balance_before) = balance_before {
+        let balance_after = ctx.client.get_balance(&payer).await?;
+        report.payer_balance = Some(PayerBalance::new(&payer, balance_before, balance_after));
+    }
+    match ctx.output {
+        OutputFormat::Text => {
+            for result in report.results.iter() {
+                result.print();
+            }
+            if is_batch {
+                report.summary.print();
+            }
+            if let Some(payer_balance) = &report.payer_balance {
+                payer_balance.print(report.cost.as_ref());
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(status)
+}
+
+/// Indexes `addresses` and returns what happened to each of them without printing it. The report
+/// is `None` if nothing was attempted, e.g. because the user declined.
+async fn index_addresses(
+    ctx: &Context,
+    rent_payer: Option<&Keypair>,
+    targets: Vec<IndexTarget>,
+    concurrency: NonZeroUsize,
+    wait_visible: Option<Duration>,
+) -> anyhow::Result<(Status, Option<IndexReport>)> {
+    let rent_payer_key = rent_payer.map(|rent_payer| rent_payer.pubkey());
+    let (entries, ineligible) =
+        resolve_eligible_entries(ctx, rent_payer_key.as_ref(), targets).await?;
+    let mut status = if ineligible.is_empty() {
+        Status::Success
+    } else {
+        Status::Invalid
+    };
+
+    let (index_status, report) =
+        index_entries(ctx, rent_payer, entries, concurrency, wait_visible).await?;
+    if index_status != Status::Success {
+        status = index_status;
+    }
+    let Some(mut report) = report else {
+        return Ok((status, None));
+    };
+    report.summary.invalid = ineligible.len();
+    report.ineligible = ineligible;
+    Ok((status, Some(report)))
+}
+
+#[derive(Serialize)]
+struct VaultIndexReport {
+    multisig: String,
+    newly_indexed: Vec<u32>,
+    already_indexed: Vec<u32>,
+    cost: Option<IndexCost>,
+    results: Vec<IndexResult>,
+}
+
+/// Indexes the authorities (vaults) of `multisig` at authority indices `0..vault_count` that
+/// aren't indexed yet, then reports which vaults were newly indexed and which already were.
+async fn index_all_vaults(
+    ctx: &Context,
+    rent_payer: Option<&Keypair>,
+    multisig: Pubkey,
+    vault_count: u32,
+    concurrency: NonZeroUsize,
+    wait_visible: Option<Duration>,
+) -> Result<Status, CliError> {
+    let Some(ms) = ctx
+        .get_account(&multisig)
+        .await?
+        .filter(|account| account.owner == ctx.squads_program_id)
+        .and_then(|account| parse_multisig(&account.data))
+    else {
+        return Err(CliError::InvalidMultisig(multisig));
+    };
+    info!("{}/{} Multisig account exists", ms.threshold, ms.keys.len());
+
+    let program_id = ctx.program_id;
+    let rent_payer_key = rent_payer.map(|rent_payer| rent_payer.pubkey());
+    let entries = (0..vault_count)
+        .map(|authority_index| {
+            let (authority_key, _) = ctx.squads_authority(&multisig, authority_index);
+            IndexEntry {
+                address: multisig,
+                multisig,
+                authority_key,
+                authority_index,
+                index_key: find_index_address(&authority_key, &program_id).0,
+                is_program: false,
+                ix: index_instruction(
+                    IndexInstruction::CreateVaultIndex { authority_index },
+                    program_id,
+                    &authority_key,
+                    &multisig,
+                    &ctx.payer.pubkey(),
+                    rent_payer_key.as_ref(),
+                ),
+            }
+        })
+        .collect::<Vec<_>>();
+    let index_keys = entries
+        .iter()
+        .map(|entry| entry.index_key)
+        .collect::<Vec<_>>();
+    let index_accounts = ctx.get_multiple_accounts(&index_keys).await?;
+    let (already_indexed, entries): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .zip(index_accounts)
+        .partition(|(_, index)| {
+            index
+                .as_ref()
+                .is_some_and(|index| index.owner == program_id)
+        });
+    let already_indexed = already_indexed
+        .into_iter()
+        .map(|(entry, _)| {
+            info!(
+                "Vault {} ({}) is already indexed",
+                entry.authority_index, entry.authority_key
+            );
+            entry.authority_index
+        })
+        .collect::<Vec<_>>();
+    let entries = entries.into_iter().map(|(entry, _)| entry).collect();
+
+    let (status, report) =
+        index_entries(ctx, rent_payer, entries, concurrency, wait_visible).await?;
+    if status == Status::Aborted {
+        return Err(CliError::Aborted);
+    }
+    let (cost, results) = match report {
+        Some(report) => (report.cost, report.results),
+        None => (None, vec![]),
+    };
+    let newly_indexed = results
+        .iter()
+        .map(|result| result.authority_index)
+        .collect::<Vec<_>>();
+    match ctx.output {
+        OutputFormat::Text => {
+            for result in results.iter() {
+                result.print();
+            }
+            let format_vaults = |vaults: &[u32]| {
+                if vaults.is_empty() {
+                    "none".to_string()
+                } else {
+                    vaults
+                        .iter()
+                        .map(|vault| vault.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            };
+            println!("Newly indexed vaults: {}", format_vaults(&newly_indexed));
+            println!(
+                "Already indexed vaults: {}",
+                format_vaults(&already_indexed)
+            );
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let report = VaultIndexReport {
+                multisig: multisig.to_string(),
+                newly_indexed,
+                already_indexed,
+                cost,
+                results,
+            };
+            println!("{}", ctx.output.to_json(&report)?);
+        }
+    }
+    Ok(status)
+}
+
+/// Estimates the cost of indexing `entries`, confirms it with the user and sends one transaction
+/// per entry. Entries that are already indexed are skipped, and an entry whose transaction fails
+/// doesn't stop the rest. Returns the report of the run, or `None` if the user declined.
+async fn index_entries(
+    ctx: &Context,
+    rent_payer: Option<&Keypair>,
+    entries: Vec<IndexEntry>,
+    concurrency: NonZeroUsize,
+    wait_visible: Option<Duration>,
+) -> anyhow::Result<(Status, Option<IndexReport>)> {
+    let client = &ctx.client;
+    let rent_payer_key = rent_payer.map(|rent_payer| rent_payer.pubkey());
+    let mut status = Status::Success;
+    let mut summary = IndexSummary::default();
+
+    // Already indexed entries won't send a transaction, so they are dropped before estimating the
+    // cost. Index accounts that were prefunded only need the rest of their rent.
+    let index_keys = entries
+        .iter()
+        .map(|entry| entry.index_key)
+        .collect::<Vec<_>>();
+    let index_accounts = ctx.get_multiple_accounts(&index_keys).await?;
+    let rent_per_entry = get_index_rent(ctx).await?;
+    let mut rent = 0;
+    let entries = entries
+        .into_iter()
+        .zip(index_accounts)
+        .filter_map(|(entry, index)| match index {
+            Some(index) if index.owner == ctx.program_id => {
+                summary.already_indexed += 1;
+                warn!(
+                    "{} already indexed, skipped",
+                    if entry.is_program {
+                        entry.address
+                    } else {
+                        entry.authority_key
+                    }
+                );
+                None
+            }
+            index => {
+                if index.as_ref().is_some_and(is_uninitialized_index) {
+                    info!(
+                        "Index account {} is funded but not initialized, completing it",
+                        entry.index_key
+                    );
+                }
+                let prefunded = index.map_or(0, |index| index.lamports);
+                rent += rent_per_entry.saturating_sub(prefunded);
+                Some(entry)
+            }
+        })
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        let report = IndexReport {
+            cost: None,
+            results: vec![],
+            ineligible: vec![],
+            summary,
+            payer_balance: None,
+        };
+        return Ok((status, Some(report)));
+    }
+    let fee_payer_key = ctx.fee_payer().pubkey();
+    let fee_per_entry = get_transaction_fee(client, &fee_payer_key, &entries[0].ix).await?;
+    let fee = fee_per_entry * entries.len() as u64;
+    let cost = IndexCost {
+        entries: entries.len(),
+        rent,
+        fees: fee,
+        total: rent + fee,
+    };
+
+    // Make sure the whole run is affordable before anything is sent. The fee payers and the rent
+    // payer may be the same account, in which case it has to cover both.
+    let rent_funder_key = rent_payer_key.unwrap_or_else(|| ctx.payer.pubkey());
+    let mut required = BTreeMap::from([(rent_funder_key, rent)]);
+    for position in 0..entries.len() {
+        *required
+            .entry(ctx.fee_payer_for(position).pubkey())
+            .or_default() += fee_per_entry;
+    }
+    for (key, lamports) in required {
+        ensure_balance(client, &key, lamports).await?;
+    }
+
+    if !ctx.skip_confirmation {
+        let mut confirmation_str = if entries.len() == 1 {
+            "Executing instruction: \n\n".to_string()
+        } else {
+            format!("Executing {} instructions: \n\n", entries.len())
+        };
+        for entry in entries.iter() {
+            if entries.len() > 1 {
+                confirmation_str.push_str(&format!("Index for {}:\n", entry.address));
+            }
+            confirmation_str.push_str(&format!("{}\n", describe_instruction(&entry.ix)));
+        }
+        confirmation_str.push_str(&format!(
+            "Cost: {} SOL (rent: {} SOL, fees: {} SOL)\n",
+            lamports_to_sol(cost.total),
+            lamports_to_sol(cost.rent),
+            lamports_to_sol(cost.fees)
+        ));
+        if let Some(rent_payer_key) = rent_payer_key {
+            confirmation_str.push_str(&format!("Rent paid by: {}\n", rent_payer_key));
+        }
+        if !ctx.fee_payer_pool.is_empty() {
+            let pool_size = ctx.fee_payer_pool.len().min(entries.len());
+            confirmation_str.push_str("Fees paid in turn by:\n");
+            for fee_payer in ctx.fee_payer_pool[..pool_size].iter() {
+                confirmation_str.push_str(&format!("  {}\n", fee_payer.pubkey()));
+            }
+        } else if ctx.fee_payer.is_some() {
+            confirmation_str.push_str(&format!("Fees paid by: {}\n", fee_payer_key));
+        }
+        if !confirm_transaction(&confirmation_str, ctx.is_mainnet().await?)? {
+            return Ok((Status::Aborted, None));
+        }
+    }
+
+    let mut signers = vec![&ctx.payer];
+    signers.extend(rent_payer);
+    let signers = &signers;
+    // Up to `concurrency` transactions are sent and confirmed at once, but the outcomes are
+    // consumed in entry order so the output and report match a sequential run. The spinner is
+    // only shown when one transaction is in flight at a time.
+    let show_spinner = concurrency.get() == 1;
+    // After Ctrl-C, or once the retry budget is used up, no new transaction is sent, but the ones
+    // in flight are still confirmed.
+    let total_entries = entries.len();
+    let addresses = entries
+        .iter()
+        .map(|entry| match entry.is_program {
+            true => entry.address.to_string(),
+            false => entry.authority_key.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let mut outcomes = futures_util::stream::iter(entries.into_iter().enumerate())
+        .take_while(|_| std::future::ready(!interrupted() && !ctx.retry_budget_exhausted()))
+        .map(|(position, entry)| async move {
+            let fee_payer = ctx.fee_payer_for(position);
+            // The index may have been created since it was checked, e.g. by another run while the
+            // prompt was open. Sending would then only pay a fee for a no-op.
+            if let Ok(Some(index)) = ctx.fetch_account(&entry.index_key).await {
+                if index.owner == ctx.program_id {
+                    return (entry, fee_payer, None, None);
+                }
+            }
+            let outcome = execute(
+                ctx,
+                entry.ix.clone(),
+                &entry.multisig,
+                signers,
+                fee_payer,
+                show_spinner,
+            )
+            .await;
+            // Only a landed transaction has an account to wait for.
+            let visible = match (&outcome, wait_visible) {
+                (Ok(Some(signature)), Some(timeout)) => {
+                    Some(wait_until_visible(ctx, &entry.index_key, signature, timeout).await)
+                }
+                _ => None,
+            };
+            (entry, fee_payer, Some(outcome), visible)
+        })
+        .buffered(concurrency.get());
+    let mut results = vec![];
+    let mut attempted = 0;
+    while let Some((entry, fee_payer, outcome, visible)) = outcomes.next().await {
+        attempted += 1;
+        let signature = match outcome {
+            None => {
+                warn!("{} already indexed, skipped", entry.authority_key);
+                summary.already_indexed += 1;
+                continue;
+            }
+            Some(Ok(Some(signature))) => signature,
+            Some(Ok(None)) => {
+                summary.failed += 1;
+                if status != Status::RpcError {
+                    status = Status::Invalid;
+                }
+                continue;
+            }
+            Some(Err(e)) => {
+                error!("Failed to index {}: {}", entry.authority_key, e);
+                summary.failed += 1;
+                status = Status::RpcError;
+                continue;
+            }
+        };
+        summary.created += 1;
+        summary.signatures.push(signature.to_string());
+        let fee_payer = (!ctx.fee_payer_pool.is_empty()).then(|| fee_payer.pubkey().to_string());
+        if let Some(fee_payer) = &fee_payer {
+            *summary.fee_payers.entry(fee_payer.clone()).or_default() += 1;
+        }
+        if visible == Some(false) {
+            warn!(
+                "Index {} for {} is confirmed but not yet readable at {} commitment",
+                entry.index_key,
+                entry.authority_key,
+                commitment_name(ctx.client.commitment())
+            );
+        }
+        results.push(IndexResult {
+            address: entry.address.to_string(),
+            authority: entry.authority_key.to_string(),
+            authority_index: entry.authority_index,
+            multisig: entry.multisig.to_string(),
+            index: entry.index_key.to_string(),
+            signature: signature.to_string(),
+            explorer_url: get_explorer_url(&signature, &ctx.network_url),
+            visible,
+            fee_payer,
+            is_program: entry.is_program,
+        });
+    }
+    // Entries are taken in order, so the ones never attempted are the tail.
+    summary.not_sent = total_entries - attempted;
+    if summary.not_sent > 0 {
+        warn!(
+            "{}: {} of {} index transactions were not sent",
+            if ctx.retry_budget_exhausted() {
+                "Retry budget used up"
+            } else {
+                "Interrupted"
+            },
+            summary.not_sent,
+            total_entries
+        );
+        summary.not_sent_addresses = addresses[attempted..].to_vec();
+    }
+    let report = IndexReport {
+        cost: Some(cost),
+        results,
+        ineligible: vec![],
+        summary,
+        payer_balance: None,
+    };
+    Ok((status, Some(report)))
+}
+
+/// Reads the addresses in a batch file: a JSON array of `{"address", "authority_index"}` objects
+/// if the file ends in `.json` or starts with `[`, and otherwise one address per line. Duplicates
+/// are dropped, keeping the first occurrence, and entries that aren't valid are reported and
+/// skipped. Fails if no valid address remains.
+fn read_address_file(path: &Path) -> anyhow::Result<Vec<IndexTarget>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+        || contents.trim_start().starts_with('[');
+    let (entries, unit) = if is_json {
+        (
+            parse_batch_json(&contents).map_err(|e| {
+                anyhow!(
+                    "{} is not a JSON array of batch entries: {}",
+                    path.display(),
+                    e
+                )
+            })?,
+            "entries",
+        )
+    } else {
+        (parse_batch_lines(&contents), "lines")
+    };
+    let mut targets = vec![];
+    let mut seen = HashSet::new();
+    let mut duplicates = 0;
+    let mut invalid = 0;
+    for (location, entry) in entries {
+        match entry {
+            Ok(target) => {
+                if seen.insert(target) {
+                    targets.push(target);
+                } else {
+                    duplicates += 1;
+                }
+            }
+            Err(reason) => {
+                warn!("Skipping {} of {}: {}", location, path.display(), reason);
+                invalid += 1;
+            }
+        }
+    }
+    info!(
+        "Parsed {} unique addresses from {} ({} duplicates, {} invalid {} skipped)",
+        targets.len(),
+        path.display(),
+        duplicates,
+        invalid,
+        unit
+    );
+    if targets.is_empty() {
+        return Err(anyhow!("No valid addresses found in {}", path.display()));
+    }
+    Ok(targets)
+}
+
+/// A batch file entry, or why it is invalid, along with where it is in the file.
+type BatchFileItem = (String, Result<IndexTarget, String>);
+
+/// Parses a batch file with one address per line. Blank lines and `#` comments are skipped.
+fn parse_batch_lines(contents: &str) -> Vec<BatchFileItem> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(line_number, line)| (line_number, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            let target = Pubkey::from_str(line)
+                .map(IndexTarget::from)
+                .map_err(|e| format!("{:?} is not a valid address ({})", line, e));
+            (format!("line {}", line_number + 1), target)
+        })
+        .collect()
+}
+
+/// Parses a JSON batch file. Only a document that isn't an array fails as a whole; each entry
+/// that doesn't match the schema is returned with the reason.
+fn parse_batch_json(contents: &str) -> serde_json::Result<Vec<BatchFileItem>> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(contents)?;
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(position, value)| {
+            let target = serde_json::from_value::<BatchFileEntry>(value)
+                .map_err(|e| e.to_string())
+                .and_then(|entry| {
+                    let address = Pubkey::from_str(&entry.address).map_err(|e| {
+                        format!("{:?} is not a valid address ({})", entry.address, e)
+                    })?;
+                    Ok(IndexTarget {
+                        authority_index: entry.authority_index,
+                        ..IndexTarget::from(address)
+                    })
+                });
+            (format!("entry {}", position + 1), target)
+        })
+        .collect())
+}
+
+/// Builds a Solana Explorer link for `signature` on the cluster behind `network_url`.
+fn get_explorer_url(signature: &Signature, network_url: &str) -> String {
+    let cluster = match Cluster::from_url(network_url) {
+        Cluster::Mainnet => String::new(),
+        Cluster::Devnet => "?cluster=devnet".to_string(),
+        Cluster::Testnet => "?cluster=testnet".to_string(),
+        Cluster::Custom => format!("?cluster=custom&customUrl={}", percent_encode(network_url)),
+    };
+    format!("https://explorer.solana.com/tx/{}{}", signature, cluster)
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Roles of the accounts of the index instructions, placed by the program's account positions.
+const INDEX_ACCOUNT_ROLES: [&str; index_accounts::RENT_PAYER + 1] = {
+    let mut roles = [""; index_accounts::RENT_PAYER + 1];
+    roles[index_accounts::SYSTEM_PROGRAM] = "System program";
+    roles[index_accounts::AUTHORITY] = "Authority (Squads Vault)";
+    roles[index_accounts::MULTISIG] = "Squads V3 multisig";
+    roles[index_accounts::PAYER] = "Payer";
+    roles[index_accounts::INDEX] = "Index PDA";
+    roles[index_accounts::RENT_PAYER] = "Rent payer";
+    roles
+};
+
+/// Roles of the accounts of `CloseIndex`, placed by the program's account positions.
+const CLOSE_ACCOUNT_ROLES: [&str; close_accounts::REQUIRED] = {
+    let mut roles = [""; close_accounts::REQUIRED];
+    roles[close_accounts::AUTHORITY] = "Authority (Squads Vault)";
+    roles[close_accounts::INDEX] = "Index PDA";
+    roles[close_accounts::RECIPIENT] = "Refund recipient";
+    roles
+};
+
+/// Names the role of each account of an index program instruction, in account order. Unknown
+/// instructions have no roles.
+fn account_roles(ix: &Instruction) -> &'static [&'static str] {
+    match IndexInstruction::unpack(&ix.data) {
+        Ok(IndexInstruction::CloseIndex) => &CLOSE_ACCOUNT_ROLES,
+        Ok(_) => &INDEX_ACCOUNT_ROLES,
+        Err(_) => &[],
+    }
+}
+
+/// Renders an index program instruction for review, labeling the role of each account along with
+/// its signer and writable flags.
+fn describe_instruction(ix: &Instruction) -> String {
+    let instruction = IndexInstruction::unpack(&ix.data).ok();
+    let roles = account_roles(ix);
+    let mut description = match instruction {
+        Some(instruction) => format!("{:?} on program {}\n", instruction, ix.program_id),
+        None => format!("Unknown instruction on program {}\n", ix.program_id),
+    };
+    let width = roles.iter().map(|role| role.len()).max().unwrap_or(0);
+    for (i, meta) in ix.accounts.iter().enumerate() {
+        let role = roles.get(i).copied().unwrap_or("Account");
+        let flags = match (meta.is_signer, meta.is_writable) {
+            (true, true) => "signer, writable",
+            (true, false) => "signer",
+            (false, true) => "writable",
+            (false, false) => "readonly",
+        };
+        description.push_str(&format!(
+            "  {}. {:<width$}  {:<44}  [{}]\n",
+            i,
+            role,
+            meta.pubkey,
+            flags,
+            width = width
+        ));
+    }
+    description
+}
+
+/// Computes the rent exemption for a single index account, using the same calculation as the
+/// on-chain program.
+async fn get_index_rent(ctx: &Context) -> anyhow::Result<u64> {
+    let rent_account = ctx
+        .get_account(&sysvar::rent::id())
+        .await?
+        .ok_or_else(|| anyhow!("Rent sysvar does not exist"))?;
+    let rent: Rent =
+        from_account(&rent_account).ok_or_else(|| anyhow!("Failed to deserialize rent sysvar"))?;
+    Ok(squads_v3_index::index_account_rent(&rent))
+}
+
+/// Fails with an insufficient funds error if `account` holds fewer than `required` lamports.
+async fn ensure_balance(client: &RpcClient, account: &Pubkey, required: u64) -> anyhow::Result<()> {
+    let balance = client.get_balance(account).await?;
+    if balance < required {
+        return Err(anyhow!(
+            "Insufficient funds in {}: need {} SOL, have {} SOL",
+            account,
+            lamports_to_sol(required),
+            lamports_to_sol(balance)
+        ));
+    }
+    Ok(())
+}
+
+/// Computes the fee for a transaction containing only `ix`.
+async fn get_transaction_fee(
+    client: &RpcClient,
+    payer: &Pubkey,
+    ix: &Instruction,
+) -> anyhow::Result<u64> {
+    let blockhash = client.get_latest_blockhash().await?;
+    let message = Message::new_with_blockhash(std::slice::from_ref(ix), Some(payer), &blockhash);
+    Ok(client.get_fee_for_message(&message).await?)
+}
+
+/// Resolves `address` to the entry that indexes its authority, or to the reason it can't be
+/// indexed. Nothing is sent, so ineligible addresses are weeded out before any SOL is spent: the
+/// multisig must be a Squads V3 `Ms` account and, for a program, derive its upgrade authority.
+async fn resolve_index_entry(
+    ctx: &Context,
+    rent_payer: Option<&Pubkey>,
+    target: IndexTarget,
+    account: Option<Account>,
+) -> anyhow::Result<Result<IndexEntry, String>> {
+    let IndexTarget {
+        address,
+        authority_index,
+        multisig: known_multisig,
+        expected_index,
+    } = target;
+    let mut is_program = false;
+    let multisig = match account {
+        Some(account_data) => {
+            if account_data.owner == ctx.squads_program_id {
+                if let Some(known) = known_multisig.filter(|known| *known != address) {
+                    return Ok(Err(format!(
+                        "is a multisig itself, not a program controlled by {}",
+                        known
+                    )));
+                }
+                address
+            } else if account_data.owner == bpf_loader_upgradeable::id()
+                && account_data.data.len() == 36
+            {
+                // The history scan finds the multisig through its vault 1, which is the one
+                // `index` links for a program.
+                if authority_index != DEFAULT_AUTHORITY_INDEX {
+                    return Ok(Err(format!(
+                        "authority_index {} only applies to multisig addresses, a program is indexed through vault {}",
+                        authority_index, DEFAULT_AUTHORITY_INDEX
+                    )));
+                }
+                let program_data = get_program_data_address(&address);
+                let Some(authority) = get_upgrade_authority(ctx, &program_data).await? else {
+                    return Ok(Err("program is immutable".to_string()));
+                };
+                if authority.is_on_curve() {
+                    return Ok(Err(format!(
+                        "upgrade authority {} is not a Program Derived Address",
+                        authority
+                    )));
+                }
+                let ms = match known_multisig {
+                    Some(ms) => {
+                        if ctx.squads_authority(&ms, DEFAULT_AUTHORITY_INDEX).0 != authority {
+                            return Ok(Err(format!(
+                                "upgrade authority {} is not vault {} of multisig {}",
+                                authority, DEFAULT_AUTHORITY_INDEX, ms
+                            )));
+                        }
+                        ms
+                    }
+                    None => {
+                        info!("Searching for multisig for {}", address);
+                        let Some(ms) =
+                            get_multisig_account_from_key(ctx, &program_data, &authority, false)
+                                .await
+                        else {
+                            return Ok(Err(format!(
+                                "no Squads V3 multisig in the program's history derives upgrade authority {}",
+                                authority
+                            )));
+                        };
+                        info!("Found multisig for {}: {}", address, ms);
+                        ms
+                    }
+                };
+                is_program = true;
+                ms
+            } else {
+                debug!("{:#?}", account_data);
+                return Ok(Err(format!(
+                    "owned by {}, not a Squads V3 multisig or an upgradeable program",
+                    account_data.owner
+                )));
+            }
+        }
+        None => return Ok(Err("account does not exist".to_string())),
+    };
+
+    // A program's multisig was only found through its address, so check the account itself too.
+    let ms_account = ctx
+        .get_account(&multisig)
+        .await?
+        .filter(|account| account.owner == ctx.squads_program_id);
+    let ms = match ms_account.map(|account| decode_multisig(&account.data)) {
+        Some(Ok(ms)) => ms,
+        Some(Err(MultisigDataError::Malformed)) => {
+            return Ok(Err(format!("{} is a malformed multisig account", multisig)));
+        }
+        _ => {
+            return Ok(Err(format!(
+                "{} is not a valid Squads V3 multisig account",
+                multisig
+            )));
+        }
+    };
+    let (authority_key, _) = ctx.squads_authority(&multisig, authority_index);
+    info!("{}/{} Multisig account exists", ms.threshold, ms.keys.len());
+    info!("Multisig key: {}", multisig);
+    info!("Authority key: {}", authority_key);
+
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority_key, &program_id).0;
+    if let Some(expected_index) = expected_index.filter(|expected| *expected != index_key) {
+        return Ok(Err(format!(
+            "derives index {} under program {}, not the expected {}. Check --program-id",
+            index_key, program_id, expected_index
+        )));
+    }
+    // Instruction to create the index account
+    let ix = index_instruction(
+        create_index_instruction(authority_index),
+        program_id,
+        &authority_key,
+        &multisig,
+        &ctx.payer.pubkey(),
+        rent_payer,
+    );
+    Ok(Ok(IndexEntry {
+        address,
+        multisig,
+        authority_key,
+        authority_index,
+        index_key,
+        is_program,
+        ix,
+    }))
+}
+
+/// The instruction that creates the index of vault `authority_index`. Vault 1 keeps the original
+/// `CreateIndex`, which older program deployments also understand.
+fn create_index_instruction(authority_index: u32) -> IndexInstruction {
+    match authority_index {
+        DEFAULT_AUTHORITY_INDEX => IndexInstruction::CreateIndex,
+        authority_index => IndexInstruction::CreateVaultIndex { authority_index },
+    }
+}
+
+#[derive(Serialize)]
+struct IneligibleAddress {
+    address: String,
+    reason: String,
+}
+
+/// Resolves every address before anything is sent, splitting them into entries that can be
+/// indexed and addresses that can't, along with why. The ineligible ones are reported right away
+/// so a batch doesn't pay fees for transactions that would fail.
+async fn resolve_eligible_entries(
+    ctx: &Context,
+    rent_payer: Option<&Pubkey>,
+    targets: Vec<IndexTarget>,
+) -> anyhow::Result<(Vec<IndexEntry>, Vec<IneligibleAddress>)> {
+    let addresses = targets
+        .iter()
+        .map(|target| target.address)
+        .collect::<Vec<_>>();
+    let accounts = ctx.get_multiple_accounts(&addresses).await?;
+    let mut eligible = vec![];
+    let mut ineligible = vec![];
+    for (target, account) in targets.into_iter().zip(accounts) {
+        match resolve_index_entry(ctx, rent_payer, target, account).await? {
+            Ok(entry) => eligible.push(entry),
+            Err(reason) => ineligible.push(IneligibleAddress {
+                address: target.address.to_string(),
+                reason,
+            }),
+        }
+    }
+    match ineligible.as_slice() {
+        [] => {}
+        [single] => error!("Can't index {}: {}", single.address, single.reason),
+        _ => {
+            let mut message = format!(
+                "Skipping {} of {} addresses that can't be indexed:",
+                ineligible.len(),
+                eligible.len() + ineligible.len()
+            );
+            for address in ineligible.iter() {
+                message.push_str(&format!("\n  {}: {}", address.address, address.reason));
+            }
+            error!("{}", message);
+        }
+    }
+    Ok((eligible, ineligible))
+}
+
+/// Re-validates an existing index account on-chain with `UpdateIndex`, which records the slot in
+/// `updated_slot`. The index address pins the multisig, so the stored multisig never changes.
+async fn reindex(ctx: &Context, address: Pubkey) -> Result<Status, CliError> {
+    let client = &ctx.client;
+    let Some(multisig) = resolve_multisig(ctx, address).await? else {
+        return Err(CliError::MultisigNotFound(address));
+    };
+    let (authority_key, _) = ctx.squads_authority(&multisig, DEFAULT_AUTHORITY_INDEX);
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority_key, &program_id).0;
+
+    let Some(index) = get_index_account(ctx, &index_key).await? else {
+        return Err(CliError::NotIndexed(authority_key));
+    };
+    if index.data.is_empty() {
+        return Err(CliError::Invalid(format!(
+            "Index {} is on the legacy layout, run `migrate` to upgrade it",
+            index_key
+        )));
+    }
+
+    let ix = index_instruction(
+        IndexInstruction::UpdateIndex,
+        program_id,
+        &authority_key,
+        &multisig,
+        &ctx.payer.pubkey(),
+        None,
+    );
+    let fee = get_transaction_fee(client, &ctx.fee_payer().pubkey(), &ix).await?;
+    ensure_balance(client, &ctx.fee_payer().pubkey(), fee).await?;
+    if !ctx.skip_confirmation {
+        let confirmation_str = format!(
+            "Executing instruction: \n\n{}\nCost: {} SOL\n",
+            describe_instruction(&ix),
+            lamports_to_sol(fee)
+        );
+        if !confirm_transaction(&confirmation_str, ctx.is_mainnet().await?)? {
+            return Err(CliError::Aborted);
+        }
+    }
+    let Some(signature) = execute(ctx, ix, &multisig, &[&ctx.payer], ctx.fee_payer(), true).await?
+    else {
+        return Ok(Status::Invalid);
+    };
+    let report = IndexMaintenance::new(ctx, authority_key, multisig, index_key, Some(signature));
+    match ctx.output {
+        OutputFormat::Text => {
+            println!(
+                "Successfully re-validated index for {} against {}",
+                authority_key, multisig
+            );
+            report.print_signature();
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(Status::Success)
+}
+
+/// What `reindex` or `migrate` did to an index, reported with `--output json`.
+#[derive(Serialize)]
+struct IndexMaintenance {
+    authority: String,
+    multisig: String,
+    index: String,
+    /// `None` when there was nothing to send, e.g. for an index that is already migrated.
+    signature: Option<String>,
+    explorer_url: Option<String>,
+}
+
+impl IndexMaintenance {
+    fn new(
+        ctx: &Context,
+        authority: Pubkey,
+        multisig: Pubkey,
+        index: Pubkey,
+        signature: Option<Signature>,
+    ) -> Self {
+        Self {
+            authority: authority.to_string(),
+            multisig: multisig.to_string(),
+            index: index.to_string(),
+            signature: signature.map(|signature| signature.to_string()),
+            explorer_url: signature.map(|signature| get_explorer_url(&signature, &ctx.network_url)),
+        }
+    }
+
+    fn print_signature(&self) {
+        if let (Some(signature), Some(explorer_url)) = (&self.signature, &self.explorer_url) {
+            println!("Signature: {}", signature);
+            println!("Explorer: {}", explorer_url);
+        }
+    }
+}
+
+/// Migrates a legacy index account, which is empty, to the current layout with `MigrateIndex`.
+/// Legacy indexes were only created for vault 1, so the multisig is resolved for that vault.
+async fn migrate(ctx: &Context, address: Pubkey) -> Result<Status, CliError> {
+    let client = &ctx.client;
+    let Some(multisig) = resolve_multisig(ctx, address).await? else {
+        return Err(CliError::MultisigNotFound(address));
+    };
+    let (authority_key, _) = ctx.squads_authority(&multisig, DEFAULT_AUTHORITY_INDEX);
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority_key, &program_id).0;
+
+    let Some(index) = get_index_account(ctx, &index_key).await? else {
+        return Err(CliError::NotIndexed(authority_key));
+    };
+    if !index.data.is_empty() {
+        match ctx.output {
+            OutputFormat::Text => println!("Index for {} is already migrated", authority_key),
+            OutputFormat::Json | OutputFormat::Jsonl => {
+                let report = IndexMaintenance::new(ctx, authority_key, multisig, index_key, None);
+                println!("{}", ctx.output.to_json(&report)?)
+            }
+        }
+        return Ok(Status::Success);
+    }
+
+    let ix = index_instruction(
+        IndexInstruction::MigrateIndex,
+        program_id,
+        &authority_key,
+        &multisig,
+        &ctx.payer.pubkey(),
+        None,
+    );
+    let top_up = get_index_rent(ctx).await?.saturating_sub(index.lamports);
+    let fee = get_transaction_fee(client, &ctx.fee_payer().pubkey(), &ix).await?;
+    // The payer funds the top-up and may also be the fee payer.
+    let mut required = BTreeMap::from([(ctx.payer.pubkey(), top_up)]);
+    *required.entry(ctx.fee_payer().pubkey()).or_default() += fee;
+    for (key, lamports) in required {
+        ensure_balance(client, &key, lamports).await?;
+    }
+    if !ctx.skip_confirmation {
+        let confirmation_str = format!(
+            "Executing instruction: \n\n{}\nCost: {} SOL ({} SOL rent top-up, {} SOL fees)\n",
+            describe_instruction(&ix),
+            lamports_to_sol(top_up + fee),
+            lamports_to_sol(top_up),
+            lamports_to_sol(fee)
+        );
+        if !confirm_transaction(&confirmation_str, ctx.is_mainnet().await?)? {
+            return Err(CliError::Aborted);
+        }
+    }
+    let Some(signature) = execute(ctx, ix, &multisig, &[&ctx.payer], ctx.fee_payer(), true).await?
+    else {
+        return Ok(Status::Invalid);
+    };
+    let report = IndexMaintenance::new(ctx, authority_key, multisig, index_key, Some(signature));
+    match ctx.output {
+        OutputFormat::Text => {
+            println!(
+                "Successfully migrated index for {}, pointing to {}",
+                authority_key, multisig
+            );
+            report.print_signature();
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(Status::Success)
+}
+
+#[derive(Serialize)]
+struct EncodedAccountMeta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    pubkey: String,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+/// An instruction in a form that can be pasted into a multisig UI or decoded by other tools.
+#[derive(Serialize)]
+struct EncodedInstruction {
+    program_id: String,
+    accounts: Vec<EncodedAccountMeta>,
+    /// Base58 encoded instruction data.
+    data: String,
+    /// The same data, base64 encoded.
+    data_base64: String,
+}
+
+impl From<&Instruction> for EncodedInstruction {
+    fn from(ix: &Instruction) -> Self {
+        let roles = account_roles(ix);
+        Self {
+            program_id: ix.program_id.to_string(),
+            accounts: ix
+                .accounts
+                .iter()
+                .enumerate()
+                .map(|(i, meta)| EncodedAccountMeta {
+                    role: roles.get(i).copied(),
+                    pubkey: meta.pubkey.to_string(),
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: bs58::encode(&ix.data).into_string(),
+            data_base64: BASE64_STANDARD.encode(&ix.data),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EncodedIndex {
+    address: String,
+    authority: String,
+    multisig: String,
+    instruction: EncodedInstruction,
+}
+
+/// Prints the `CreateIndex` instruction for each of `addresses` without sending anything, for a
+/// member to propose as a Squads transaction.
+///
+/// The Squads Vault is the payer: when the multisig executes the proposal the Squads V3 program
+/// signs for the vault, so the rent comes out of the vault rather than a personal keypair.
+/// Authorities that are already indexed are skipped.
+async fn encode_index(ctx: &Context, targets: Vec<IndexTarget>) -> Result<Status, CliError> {
+    let (entries, ineligible) = resolve_eligible_entries(ctx, None, targets).await?;
+    let status = if ineligible.is_empty() {
+        Status::Success
+    } else {
+        Status::Invalid
+    };
+    let index_keys = entries
+        .iter()
+        .map(|entry| entry.index_key)
+        .collect::<Vec<_>>();
+    let index_accounts = ctx.get_multiple_accounts(&index_keys).await?;
+
+    let mut encoded = vec![];
+    for (entry, index) in entries.into_iter().zip(index_accounts) {
+        if index.is_some_and(|index| index.owner == ctx.program_id) {
+            warn!("{} already indexed, skipped", entry.authority_key);
+            continue;
+        }
+        let ix = index_instruction(
+            create_index_instruction(entry.authority_index),
+            ctx.program_id,
+            &entry.authority_key,
+            &entry.multisig,
+            &entry.authority_key,
+            None,
+        );
+        if ctx.output == OutputFormat::Text {
+            println!(
+                "Propose this instruction as a transaction of multisig {}:",
+                entry.multisig
+            );
+            println!();
+            print!("{}", describe_instruction(&ix));
+            println!("Data (base64): {}", BASE64_STANDARD.encode(&ix.data));
+            println!();
+        }
+        encoded.push(EncodedIndex {
+            address: entry.address.to_string(),
+            authority: entry.authority_key.to_string(),
+            multisig: entry.multisig.to_string(),
+            instruction: EncodedInstruction::from(&ix),
+        });
+    }
+    match ctx.output {
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", ctx.output.to_json(&encoded)?),
+        OutputFormat::Jsonl => {
+            for encoded in encoded.iter() {
+                println!("{}", ctx.output.to_json(encoded)?);
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// An index transaction signed by `index --sign-only`, as stored in its out file.
+#[derive(Serialize, Deserialize)]
+struct SignedIndex {
+    address: String,
+    authority: String,
+    authority_index: u32,
+    multisig: String,
+    index: String,
+    is_program: bool,
+    /// The signed transaction, bincode serialized and base64 encoded.
+    transaction: String,
+}
+
+/// Signs the index transactions for `addresses` and writes them to `out_file`, so that `submit`
+/// can broadcast them from a machine that doesn't hold the keys. Addresses that are already
+/// indexed are skipped.
+async fn sign_index(
+    ctx: &Context,
+    rent_payer: Option<&Keypair>,
+    targets: Vec<IndexTarget>,
+    out_file: &Path,
+) -> Result<Status, CliError> {
+    let rent_payer_key = rent_payer.map(|rent_payer| rent_payer.pubkey());
+    let (entries, ineligible) =
+        resolve_eligible_entries(ctx, rent_payer_key.as_ref(), targets).await?;
+    let status = if ineligible.is_empty() {
+        Status::Success
+    } else {
+        Status::Invalid
+    };
+    let index_keys = entries
+        .iter()
+        .map(|entry| entry.index_key)
+        .collect::<Vec<_>>();
+    let index_accounts = ctx.get_multiple_accounts(&index_keys).await?;
+
+    let mut signers = vec![&ctx.payer];
+    signers.extend(rent_payer);
+    let blockhash = ctx.latest_blockhash().await?;
+    let mut signed = vec![];
+    for (entry, index) in entries.into_iter().zip(index_accounts) {
+        if index.is_some_and(|index| index.owner == ctx.program_id) {
+            warn!("{} already indexed, skipped", entry.authority_key);
+            continue;
+        }
+        let tx = sign_transaction(ctx.fee_payer(), &entry.ix, &signers, blockhash);
+        let bytes = bincode::serialize(&tx)
+            .map_err(|e| anyhow!("Failed to serialize transaction: {}", e))?;
+        signed.push(SignedIndex {
+            address: entry.address.to_string(),
+            authority: entry.authority_key.to_string(),
+            authority_index: entry.authority_index,
+            multisig: entry.multisig.to_string(),
+            index: entry.index_key.to_string(),
+            is_program: entry.is_program,
+            transaction: BASE64_STANDARD.encode(bytes),
+        });
+    }
+    std::fs::write(out_file, serde_json::to_string_pretty(&signed)?)
+        .map_err(|e| anyhow!("Failed to write {}: {}", out_file.display(), e))?;
+    match ctx.output {
+        OutputFormat::Text => {
+            for signed in signed.iter() {
+                println!("Signed index transaction for {}", signed.address);
+            }
+            println!(
+                "Wrote {} signed transactions to {}",
+                signed.len(),
+                out_file.display()
+            );
+            println!(
+                "Submit them with `squads-v3-index-cli submit {}` before blockhash {} expires",
+                out_file.display(),
+                blockhash
+            );
+        }
+        OutputFormat::Json => println!("{}", ctx.output.to_json(&signed)?),
+        OutputFormat::Jsonl => {
+            for signed in signed.iter() {
+                println!("{}", ctx.output.to_json(signed)?);
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Decodes the transaction of `signed` and checks that it is fully signed and has a fee payer, so
+/// it can be broadcast as is.
+fn decode_signed_transaction(signed: &SignedIndex) -> Result<Transaction, CliError> {
+    let tx = BASE64_STANDARD
+        .decode(&signed.transaction)
+        .ok()
+        .and_then(|bytes| bincode::deserialize::<Transaction>(&bytes).ok())
+        .ok_or_else(|| {
+            CliError::Invalid(format!(
+                "The transaction for {} can't be decoded",
+                signed.address
+            ))
+        })?;
+    // `is_signed` holds for a transaction without signatures, which has no signature to report
+    // and no fee payer to charge.
+    if tx.signatures.is_empty() || tx.message.account_keys.is_empty() {
+        return Err(CliError::Invalid(format!(
+            "The transaction for {} has no signatures or accounts",
+            signed.address
+        )));
+    }
+    if !tx.is_signed() {
+        return Err(CliError::Invalid(format!(
+            "The transaction for {} is missing signatures",
+            signed.address
+        )));
+    }
+    Ok(tx)
+}
+
+/// Broadcasts the transactions that `index --sign-only` wrote to `path`. They can't be signed
+/// again here, so a transaction whose blockhash has expired is reported instead of sent.
+async fn submit(ctx: &Context, path: &Path) -> Result<Status, CliError> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let signed: Vec<SignedIndex> = serde_json::from_str(&contents).map_err(|e| {
+        CliError::Invalid(format!(
+            "{} is not a file written by index --sign-only: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let mut transactions = Vec::with_capacity(signed.len());
+    for signed in signed {
+        let tx = decode_signed_transaction(&signed)?;
+        let multisig = Pubkey::from_str(&signed.multisig).map_err(|_| {
+            CliError::Invalid(format!(
+                "Invalid multisig {} for {}",
+                signed.multisig, signed.address
+            ))
+        })?;
+        transactions.push((signed, multisig, tx));
+    }
+    if transactions.is_empty() {
+        info!("No transactions to submit");
+        return Ok(Status::Success);
+    }
+    if !ctx.skip_confirmation {
+        let mut confirmation_str = format!(
+            "Submitting {} signed transactions: \n\n",
+            transactions.len()
+        );
+        for (signed, _, tx) in transactions.iter() {
+            confirmation_str.push_str(&format!(
+                "Index for {}, fees paid by {}\n",
+                signed.address, tx.message.account_keys[0]
+            ));
+        }
+        if !confirm_transaction(&confirmation_str, ctx.is_mainnet().await?)? {
+            return Err(CliError::Aborted);
+        }
+    }
+
+    let commitment = ctx.client.commitment();
+    let mut status = Status::Success;
+    let mut results = vec![];
+    for (signed, multisig, tx) in transactions {
+        if interrupted() {
+            warn!("Not submitting the remaining transactions");
+            break;
+        }
+        let blockhash = tx.message.recent_blockhash;
+        if !ctx
+            .client
+            .is_blockhash_valid(&blockhash, commitment)
+            .await?
+        {
+            warn!(
+                "Blockhash {} of the transaction for {} has expired, so it can't land anymore. Sign it again with index --sign-only",
+                blockhash, signed.address
+            );
+            status = Status::Invalid;
+            continue;
+        }
+        let signature = match send_with_retries(ctx, tx, &multisig, None, true).await {
+            Ok(Some(signature)) => signature,
+            Ok(None) => {
+                if status != Status::RpcError {
+                    status = Status::Invalid;
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Failed to submit index for {}: {}", signed.address, e);
+                status = Status::RpcError;
+                continue;
+            }
+        };
+        results.push(IndexResult {
+            address: signed.address,
+            authority: signed.authority,
+            authority_index: signed.authority_index,
+            multisig: signed.multisig,
+            index: signed.index,
+            signature: signature.to_string(),
+            explorer_url: get_explorer_url(&signature, &ctx.network_url),
+            visible: None,
+            fee_payer: None,
+            is_program: signed.is_program,
+        });
+    }
+    match ctx.output {
+        OutputFormat::Text => {
+            for result in results.iter() {
+                result.print();
+            }
+        }
+        OutputFormat::Json => println!("{}", ctx.output.to_json(&results)?),
+        OutputFormat::Jsonl => {
+            for result in results.iter() {
+                println!("{}", ctx.output.to_json(result)?);
+            }
+        }
+    }
+    Ok(status)
+}
+
+#[derive(Serialize)]
+struct UnindexPlan {
+    index: String,
+    authority: String,
+    multisig: String,
+    refund: String,
+    lamports: u64,
+    instruction: EncodedInstruction,
+}
+
+/// Prepares the `CloseIndex` instruction for the index behind `address`, refunding its rent to
+/// `refund` or the payer.
+///
+/// The program only lets the authority close its index, and the authority is a Squads PDA that
+/// only the Squads V3 program can sign for. The instruction therefore can't be sent from here: it
+/// is printed for a member of the multisig to propose as a Squads transaction. The payer must be a
+/// member, so only people who can actually propose the close go through with it.
+async fn unindex(
+    ctx: &Context,
+    address: Pubkey,
+    refund: Option<Pubkey>,
+) -> Result<Status, CliError> {
+    let refund = refund.unwrap_or_else(|| ctx.payer.pubkey());
+    // A multisig is unindexed through the authority `index` creates for it.
+    let authority = match ctx.get_account(&address).await? {
+        Some(account) if account.owner == ctx.squads_program_id => {
+            ctx.squads_authority(&address, DEFAULT_AUTHORITY_INDEX).0
+        }
+        _ => match resolve_check_authority(ctx, address).await? {
+            Some((authority, _)) => authority,
+            None => {
+                return Err(CliError::ImmutableProgram);
+            }
+        },
+    };
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority, &program_id).0;
+    let Some(index) = get_index_account(ctx, &index_key).await? else {
+        return Err(CliError::NotIndexed(authority));
+    };
+    if refund == index_key {
+        return Err(CliError::Invalid(
+            "The refund account can't be the index account itself".to_string(),
+        ));
+    }
+
+    // Legacy index accounts don't store their multisig, so it is recovered from history.
+    let multisig = match IndexAccount::try_from_account_data(&index.data) {
+        Ok(index_account) => index_account.multisig,
+        Err(_) => match resolve_multisig(ctx, authority).await? {
+            Some(multisig) => multisig,
+            None => {
+                return Err(CliError::MultisigNotFound(authority));
+            }
+        },
+    };
+    let Some(ms) = ctx
+        .get_account(&multisig)
+        .await?
+        .and_then(|account| parse_multisig(&account.data))
+    else {
+        return Err(CliError::InvalidMultisig(multisig));
+    };
+    if !ms.keys.contains(&ctx.payer.pubkey()) {
+        return Err(CliError::Invalid(format!(
+            "{} is not a member of multisig {}, so it can't propose closing the index",
+            ctx.payer.pubkey(),
+            multisig
+        )));
+    }
+
+    let ix = close_instruction(program_id, &authority, &refund);
+    let plan = UnindexPlan {
+        index: index_key.to_string(),
+        authority: authority.to_string(),
+        multisig: multisig.to_string(),
+        refund: refund.to_string(),
+        lamports: index.lamports,
+        instruction: EncodedInstruction::from(&ix),
+    };
+    match ctx.output {
+        OutputFormat::Text => {
+            println!(
+                "Closing index {} reclaims {} lamports ({} SOL), refunded to {}",
+                index_key,
+                index.lamports,
+                lamports_to_sol(index.lamports),
+                refund
+            );
+            println!();
+            println!(
+                "The close must be signed by authority {}. Propose this instruction as a transaction of multisig {}:",
+                authority, multisig
+            );
+            println!();
+            print!("{}", describe_instruction(&ix));
+            println!("Data (base58): {}", plan.instruction.data);
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&plan)?),
+    }
+    Ok(Status::Success)
+}
+
+/// What `check` found for an address.
+enum CheckOutcome {
+    /// The program is immutable, so there is nothing to index.
+    Immutable,
+    /// The authority is not a Program Derived Address, so it can't be a Squads authority.
+    NotPda,
+    /// The upgrade authority is a PDA that no multisig in the program's history derives.
+    NotSquadsAuthority,
+    /// The upgrade authority isn't indexed and is a vault of a multisig other than vault 1, which
+    /// is the one `index` links for a program.
+    OtherVault {
+        multisig: Pubkey,
+        authority_index: u32,
+    },
+    /// The index PDA holds lamports but was never initialized.
+    Uninitialized {
+        lamports: u64,
+    },
+    NotIndexed,
+    Indexed {
+        /// `None` for legacy empty index accounts and data that can't be read.
+        index_account: Option<IndexAccount>,
+        /// The index account is empty, i.e. on the layout before the index stored any data.
+        legacy: bool,
+        /// The indexed multisig, read from the index account or recovered from history.
+        multisig: Option<Pubkey>,
+        /// The multisig's account, if it parses.
+        multisig_account: Option<Ms>,
+    },
+}
+
+/// Result of checking an address, returned by [`check_index`] so it can be used without the
+/// printing done by `check`. `check` prints it as text, or as its [`CheckReport`] for JSON.
+struct CheckResult {
+    address: Pubkey,
+    is_program: bool,
+    /// `None` when the program is immutable.
+    authority: Option<Pubkey>,
+    /// Index PDA of the authority, `None` when there is no authority to index.
+    index: Option<Pubkey>,
+    outcome: CheckOutcome,
+}
+
+/// What `check` prints with `--output json`, built from a [`CheckResult`].
+#[derive(Debug, PartialEq, Serialize)]
+struct CheckReport {
+    address: String,
+    is_program: bool,
+    /// The outcome, e.g. `indexed` or `not_indexed`.
+    state: &'static str,
+    authority: Option<String>,
+    index: Option<String>,
+    /// The indexed multisig, or for `other_vault` the multisig the authority is a vault of.
+    multisig: Option<String>,
+    authority_index: Option<u32>,
+    threshold: Option<u16>,
+    members: Vec<String>,
+    created_slot: Option<Slot>,
+    updated_slot: Option<Slot>,
+    /// The index account uses the legacy empty layout and needs `migrate`.
+    legacy: bool,
+    /// Lamports held by an index account that was never initialized.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lamports: Option<u64>,
+}
+
+impl CheckResult {
+    fn report(&self) -> CheckReport {
+        let mut report = CheckReport {
+            address: self.address.to_string(),
+            is_program: self.is_program,
+            state: "",
+            authority: self.authority.map(|authority| authority.to_string()),
+            index: self.index.map(|index| index.to_string()),
+            multisig: None,
+            authority_index: None,
+            threshold: None,
+            members: vec![],
+            created_slot: None,
+            updated_slot: None,
+            legacy: false,
+            lamports: None,
+        };
+        report.state = match &self.outcome {
+            CheckOutcome::Immutable => "immutable",
+            CheckOutcome::NotPda => "not_pda",
+            CheckOutcome::NotSquadsAuthority => "not_squads_authority",
+            CheckOutcome::OtherVault {
+                multisig,
+                authority_index,
+            } => {
+                report.multisig = Some(multisig.to_string());
+                report.authority_index = Some(*authority_index);
+                "other_vault"
+            }
+            CheckOutcome::Uninitialized { lamports } => {
+                report.lamports = Some(*lamports);
+                "uninitialized"
+            }
+            CheckOutcome::NotIndexed => "not_indexed",
+            CheckOutcome::Indexed {
+                index_account,
+                legacy,
+                multisig,
+                multisig_account,
+            } => {
+                report.multisig = multisig.map(|multisig| multisig.to_string());
+                report.legacy = *legacy;
+                if let Some(index_account) = index_account {
+                    report.authority_index = Some(index_account.authority_index);
+                    report.created_slot = Some(index_account.created_slot);
+                    report.updated_slot =
+                        Some(index_account.updated_slot).filter(|slot| *slot != 0);
+                }
+                if let Some(ms) = multisig_account {
+                    report.threshold = Some(ms.threshold);
+                    report.members = ms.keys.iter().map(Pubkey::to_string).collect();
+                }
+                "indexed"
+            }
+        };
+        report
+    }
+
+    fn status(&self) -> Status {
+        match self.outcome {
+            CheckOutcome::Immutable | CheckOutcome::Indexed { .. } => Status::Success,
+            CheckOutcome::NotPda | CheckOutcome::NotSquadsAuthority => Status::Invalid,
+            CheckOutcome::OtherVault { .. }
+            | CheckOutcome::Uninitialized { .. }
+            | CheckOutcome::NotIndexed => Status::NotIndexed,
+        }
+    }
+
+    fn print(&self) {
+        let authority = self.authority.unwrap_or_default();
+        match &self.outcome {
+            CheckOutcome::Immutable => println!("Program is immutable {}", mark(true)),
+            CheckOutcome::NotPda => println!(
+                "Authority {} is not a Program Derived Address {}",
+                authority,
+                mark(false)
+            ),
+            CheckOutcome::NotSquadsAuthority => println!(
+                "Upgrade authority {} is a PDA but not a Squads V3 authority: no multisig in the program's history derives it {}",
+                authority,
+                mark(false)
+            ),
+            CheckOutcome::OtherVault {
+                multisig,
+                authority_index,
+            } => println!(
+                "Not indexed at vault 1, but upgrade authority {} matches vault {} of multisig {} {}. Index it with a JSON --file entry {{\"address\": \"{}\", \"authority_index\": {}}}",
+                authority,
+                authority_index,
+                multisig,
+                mark(false),
+                multisig,
+                authority_index
+            ),
+            CheckOutcome::Uninitialized { lamports } => println!(
+                "Index account for {} holds {} SOL but was never initialized {}. Run `index` to finish it",
+                authority,
+                lamports_to_sol(*lamports),
+                mark(false)
+            ),
+            CheckOutcome::NotIndexed => println!(
+                "Index account does not exist for {} {}",
+                authority,
+                mark(false)
+            ),
+            CheckOutcome::Indexed {
+                index_account,
+                legacy,
+                multisig,
+                multisig_account,
+            } => {
+                println!("Index account exists for {} {}", authority, mark(true));
+                if self.is_program {
+                    println!();
+                    println!("{} is controlled by a Squads multisig", self.address);
+                }
+                if *legacy {
+                    println!();
+                    println!(
+                        "The index account uses the legacy empty layout and doesn't record its multisig. Run `migrate {}` to store it on-chain",
+                        self.address
+                    );
+                }
+                println!();
+                if let Some(index_account) = index_account {
+                    println!(
+                        "Indexed multisig: {} (authority index {})",
+                        index_account.multisig, index_account.authority_index
+                    );
+                    println!("Created at slot: {}", index_account.created_slot);
+                    if index_account.updated_slot != 0 {
+                        println!("Updated at slot: {}", index_account.updated_slot);
+                    }
+                    println!();
+                }
+                if let (Some(multisig), Some(multisig_account)) = (multisig, multisig_account) {
+                    println!("Multisig details");
+                    println!("Address: {}", multisig);
+                    print_members(multisig_account);
+                }
+            }
+        }
+    }
+}
+
+async fn check(ctx: &Context, address: Pubkey, members_only: bool) -> Result<Status, CliError> {
+    let result = check_index(ctx, address).await?;
+    if members_only {
+        return print_check_members(ctx, &result);
+    }
+    match ctx.output {
+        OutputFormat::Text => result.print(),
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            println!("{}", ctx.output.to_json(&result.report())?)
+        }
+    }
+    Ok(result.status())
+}
+
+/// Whether one vault of a multisig is indexed, as reported by `check --all`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum VaultIndexState {
+    Indexed,
+    NotIndexed,
+    /// The index PDA holds lamports but was never initialized.
+    Uninitialized,
+    /// The index exists but points to a different multisig.
+    OtherMultisig,
+}
+
+#[derive(Serialize)]
+struct VaultStatus {
+    authority_index: u32,
+    authority: String,
+    index: String,
+    state: VaultIndexState,
+    /// The multisig the index points to instead, for `other_multisig`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    indexed_multisig: Option<String>,
+}
+
+#[derive(Serialize)]
+struct VaultsReport {
+    multisig: String,
+    vaults: Vec<VaultStatus>,
+}
+
+/// Checks the index of every authority (vault) of `multisig` at authority indices
+/// `0..vault_count`, by default every vault the multisig has created, and prints them as a table.
+/// Succeeds only if all of them are indexed.
+async fn check_all_vaults(
+    ctx: &Context,
+    multisig: Pubkey,
+    vault_count: Option<u32>,
+) -> Result<Status, CliError> {
+    let Some(ms) = ctx
+        .get_account(&multisig)
+        .await?
+        .filter(|account| account.owner == ctx.squads_program_id)
+        .and_then(|account| parse_multisig(&account.data))
+    else {
+        return Err(CliError::InvalidMultisig(multisig));
+    };
+    // The multisig records the highest authority index it has handed out.
+    let vault_count = vault_count.unwrap_or(u32::from(ms.authority_index) + 1);
+    let authorities = (0..vault_count)
+        .map(|authority_index| {
+            let (authority, _) = ctx.squads_authority(&multisig, authority_index);
+            let index = find_index_address(&authority, &ctx.program_id).0;
+            (authority_index, authority, index)
+        })
+        .collect::<Vec<_>>();
+    let index_keys = authorities
+        .iter()
+        .map(|(_, _, index)| *index)
+        .collect::<Vec<_>>();
+    let index_accounts = ctx.get_multiple_accounts(&index_keys).await?;
+
+    let mut vaults = vec![];
+    for ((authority_index, authority, index), account) in
+        authorities.into_iter().zip(index_accounts)
+    {
+        let (state, indexed_multisig) = match account {
+            Some(account) if account.owner == ctx.program_id => {
+                // Legacy index accounts are empty and don't record their multisig.
+                match IndexAccount::try_from_account_data(&account.data) {
+                    Ok(index_account) if index_account.multisig != multisig => (
+                        VaultIndexState::OtherMultisig,
+                        Some(index_account.multisig.to_string()),
+                    ),
+                    _ => (VaultIndexState::Indexed, None),
+                }
+            }
+            Some(account) if is_uninitialized_index(&account) => {
+                (VaultIndexState::Uninitialized, None)
+            }
+            _ => (VaultIndexState::NotIndexed, None),
+        };
+        vaults.push(VaultStatus {
+            authority_index,
+            authority: authority.to_string(),
+            index: index.to_string(),
+            state,
+            indexed_multisig,
+        });
+    }
+    let status = if vaults
+        .iter()
+        .all(|vault| vault.state == VaultIndexState::Indexed)
+    {
+        Status::Success
+    } else {
+        Status::NotIndexed
+    };
+
+    let report = VaultsReport {
+        multisig: multisig.to_string(),
+        vaults,
+    };
+    match ctx.output {
+        OutputFormat::Text => {
+            println!("Vaults of multisig {}", report.multisig);
+            println!();
+            println!("{:<6} {:<44}  Index", "Vault", "Authority");
+            for vault in report.vaults.iter() {
+                let state = match vault.state {
+                    VaultIndexState::Indexed => format!("indexed {}", mark(true)),
+                    VaultIndexState::NotIndexed => format!("not indexed {}", mark(false)),
+                    VaultIndexState::Uninitialized => {
+                        format!("funded but not initialized {}", mark(false))
+                    }
+                    VaultIndexState::OtherMultisig => format!(
+                        "points to {} {}",
+                        vault.indexed_multisig.as_deref().unwrap_or_default(),
+                        mark(false)
+                    ),
+                };
+                println!(
+                    "{:<6} {:<44}  {}",
+                    vault.authority_index, vault.authority, state
+                );
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(status)
+}
+
+/// Prints only the members of the multisig found by `check`, for piping into other tools. Any
+/// outcome without a readable multisig is an error, so nothing is printed on stdout for it.
+fn print_check_members(ctx: &Context, result: &CheckResult) -> Result<Status, CliError> {
+    let authority = result.authority.unwrap_or_default();
+    match &result.outcome {
+        CheckOutcome::Indexed {
+            multisig_account: Some(_),
+            ..
+        } => {}
+        CheckOutcome::Indexed {
+            multisig: Some(multisig),
+            multisig_account: None,
+            ..
+        } => return Err(CliError::InvalidMultisig(*multisig)),
+        CheckOutcome::Indexed { multisig: None, .. } => {
+            return Err(CliError::MultisigNotFound(authority))
+        }
+        CheckOutcome::Immutable => return Err(CliError::ImmutableProgram),
+        CheckOutcome::NotPda => {
+            return Err(CliError::Invalid(format!(
+                "Authority {} is not a Program Derived Address",
+                authority
+            )))
+        }
+        CheckOutcome::NotSquadsAuthority => {
+            return Err(CliError::Invalid(format!(
+                "Upgrade authority {} is a PDA but not a Squads V3 authority",
+                authority
+            )))
+        }
+        CheckOutcome::OtherVault { .. }
+        | CheckOutcome::Uninitialized { .. }
+        | CheckOutcome::NotIndexed => return Err(CliError::NotIndexed(authority)),
+    }
+    let members = result.report().members;
+    match ctx.output {
+        OutputFormat::Text => {
+            for member in members.iter() {
+                println!("{}", member);
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&members)?),
+    }
+    Ok(Status::Success)
+}
+
+/// Resolves the authority behind `address` and reports whether it is indexed, along with the
+/// indexed multisig.
+async fn check_index(ctx: &Context, address: Pubkey) -> anyhow::Result<CheckResult> {
+    let Some((authority, is_program)) = resolve_check_authority(ctx, address).await? else {
+        return Ok(CheckResult {
+            address,
+            is_program: true,
+            authority: None,
+            index: None,
+            outcome: CheckOutcome::Immutable,
+        });
+    };
+    let mut result = CheckResult {
+        address,
+        is_program,
+        authority: Some(authority),
+        index: None,
+        outcome: CheckOutcome::NotPda,
+    };
+    if authority.is_on_curve() {
+        return Ok(result);
+    }
+
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority, &program_id).0;
+    result.index = Some(index_key);
+
+    let Some(index) = get_index_account(ctx, &index_key).await? else {
+        // Any program can own a PDA upgrade authority, so look for the multisig that derives it
+        // before reporting the program as merely not indexed.
+        if is_program {
+            let program_data = get_program_data_address(&address);
+            match find_authority_in_history(ctx, &program_data, &authority, false).await {
+                Some((_, 1)) => {}
+                Some((multisig, authority_index)) => {
+                    result.outcome = CheckOutcome::OtherVault {
+                        multisig,
+                        authority_index,
+                    };
+                    return Ok(result);
+                }
+                None => {
+                    result.outcome = CheckOutcome::NotSquadsAuthority;
+                    return Ok(result);
+                }
+            }
+        }
+        result.outcome = match ctx.get_account(&index_key).await? {
+            Some(account) if is_uninitialized_index(&account) => CheckOutcome::Uninitialized {
+                lamports: account.lamports,
+            },
+            _ => CheckOutcome::NotIndexed,
+        };
+        return Ok(result);
+    };
+    // Prefer the multisig stored in the index account. Legacy index accounts are empty, so
+    // their multisig can only be recovered from transaction history.
+    let (index_account, multisig) = if index.data.is_empty() {
+        let multisig = get_multisig_account_from_key(ctx, &index_key, &authority, true).await;
+        (None, multisig)
+    } else {
+        match IndexAccount::try_from_account_data(&index.data) {
+            Ok(index_account) => {
+                let multisig = index_account.multisig;
+                (Some(index_account), Some(multisig))
+            }
+            Err(e) => {
+                warn!("Index account {} {}", index_key, index_data_error(&e));
+                (None, None)
+            }
+        }
+    };
+    let multisig_account = match multisig {
+        Some(multisig_addr) => {
+            let account_data = ctx
+                .get_account(&multisig_addr)
+                .await?
+                .ok_or_else(|| anyhow!("Multisig account {} does not exist", multisig_addr))?;
+            parse_multisig(&account_data.data)
+        }
+        None => None,
+    };
+    result.outcome = CheckOutcome::Indexed {
+        index_account,
+        legacy: index.data.is_empty(),
+        multisig,
+        multisig_account,
+    };
+    Ok(result)
+}
+
+/// Polls the index account for `address` every `interval` seconds, printing each change in its
+/// indexed status until it becomes indexed. If `websocket_url` is set, an account subscription is
+/// used instead of polling.
+async fn watch_index(
+    ctx: &Context,
+    address: Pubkey,
+    interval: u64,
+    websocket_url: Option<&str>,
+) -> Result<Status, CliError> {
+    let Some((authority, _)) = resolve_check_authority(ctx, address).await? else {
+        let event = WatchEvent::new(None, None, "immutable");
+        event.print(ctx, format!("Program is immutable {}", mark(true)))?;
+        return Ok(Status::Success);
+    };
+    if authority.is_on_curve() {
+        let event = WatchEvent::new(Some(&authority), None, "not_pda");
+        event.print(
+            ctx,
+            format!(
+                "Authority {} is not a Program Derived Address {}",
+                authority,
+                mark(false)
+            ),
+        )?;
+        return Ok(Status::Invalid);
+    }
+
+    let index_key = find_index_address(&authority, &ctx.program_id).0;
+    let stopped = || {
+        WatchEvent::new(Some(&authority), Some(&index_key), "stopped").print(
+            ctx,
+            format!("Stopped watching {} before it was indexed", authority),
+        )
+    };
+
+    if let Some(websocket_url) = websocket_url {
+        tokio::select! {
+            result = subscribe_index(
+                ctx,
+                websocket_url,
+                &authority,
+                &index_key,
+                interval,
+            ) => result?,
+            _ = wait_for_interrupt() => {
+                stopped()?;
+                return Ok(Status::Interrupted);
+            }
+        }
+        return Ok(Status::Success);
+    }
+
+    let mut last_status = None;
+    loop {
+        let indexed = is_indexed(ctx, &index_key).await;
+        if last_status != Some(indexed) {
+            WatchEvent::indexed(ctx, &authority, &index_key, indexed)?;
+            last_status = Some(indexed);
+        }
+        if indexed {
+            return Ok(Status::Success);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => {}
+            _ = wait_for_interrupt() => {
+                stopped()?;
+                return Ok(Status::Interrupted);
+            }
+        }
+    }
+}
+
+/// A state reached by `check --watch`, printed as a JSON line per change with `--output json`.
+#[derive(Serialize)]
+struct WatchEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authority: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<String>,
+    /// `indexed`, `not_indexed`, `stopped`, `immutable` or `not_pda`.
+    state: &'static str,
+}
+
+impl WatchEvent {
+    fn new(authority: Option<&Pubkey>, index: Option<&Pubkey>, state: &'static str) -> Self {
+        Self {
+            authority: authority.map(Pubkey::to_string),
+            index: index.map(Pubkey::to_string),
+            state,
+        }
+    }
+
+    /// Reports whether the index account of `authority` exists.
+    fn indexed(
+        ctx: &Context,
+        authority: &Pubkey,
+        index: &Pubkey,
+        indexed: bool,
+    ) -> anyhow::Result<()> {
+        let (state, text) = match indexed {
+            true => ("indexed", "Index account exists for"),
+            false => ("not_indexed", "Index account does not exist for"),
+        };
+        Self::new(Some(authority), Some(index), state)
+            .print(ctx, format!("{} {} {}", text, authority, mark(indexed)))
+    }
+
+    /// Prints `text`, or the event itself unless the output is text.
+    fn print(&self, ctx: &Context, text: String) -> anyhow::Result<()> {
+        match ctx.output {
+            OutputFormat::Text => println!("{}", text),
+            // Each event is a line of its own, so it can be consumed as it happens.
+            OutputFormat::Json | OutputFormat::Jsonl => {
+                println!("{}", OutputFormat::Jsonl.to_json(self)?)
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waits for the index account to be created by subscribing to it over the RPC WebSocket
+/// endpoint. If the socket drops, it reconnects after `retry_interval` seconds.
+async fn subscribe_index(
+    ctx: &Context,
+    websocket_url: &str,
+    authority: &Pubkey,
+    index_key: &Pubkey,
+    retry_interval: u64,
+) -> anyhow::Result<()> {
+    let mut reported_missing = false;
+    loop {
+        let pubsub = match PubsubClient::new(websocket_url).await {
+            Ok(pubsub) => pubsub,
+            Err(e) => {
+                warn!("Failed to connect to {}: {}", websocket_url, e);
+                tokio::time::sleep(Duration::from_secs(retry_interval)).await;
+                continue;
+            }
+        };
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(ctx.client.commitment()),
+            ..RpcAccountInfoConfig::default()
+        };
+        let (mut updates, unsubscribe) =
+            match pubsub.account_subscribe(index_key, Some(config)).await {
+                Ok(subscription) => subscription,
+                Err(e) => {
+                    warn!("Failed to subscribe to {}: {}", index_key, e);
+                    tokio::time::sleep(Duration::from_secs(retry_interval)).await;
+                    continue;
+                }
+            };
+
+        // Check after subscribing so a creation between the two can't be missed.
+        if is_indexed(ctx, index_key).await {
+            unsubscribe().await;
+            return WatchEvent::indexed(ctx, authority, index_key, true);
+        }
+        if !reported_missing {
+            WatchEvent::indexed(ctx, authority, index_key, false)?;
+            reported_missing = true;
+        }
+
+        while let Some(update) = updates.next().await {
+            if update.value.owner == ctx.program_id.to_string() {
+                unsubscribe().await;
+                return WatchEvent::indexed(ctx, authority, index_key, true);
+            }
+        }
+        warn!("Subscription dropped, reconnecting to {}", websocket_url);
+        tokio::time::sleep(Duration::from_secs(retry_interval)).await;
+    }
+}
+
+/// Resolves the authority to check for `address`, reading the upgrade authority if it is a
+/// program. Returns `None` if the program is immutable. The flag is set when `address` is a program.
+async fn resolve_check_authority(
+    ctx: &Context,
+    address: Pubkey,
+) -> anyhow::Result<Option<(Pubkey, bool)>> {
+    match ctx.get_account(&address).await? {
+        // Allow user to pass in a program ID
+        Some(a) if a.owner == bpf_loader_upgradeable::id() && a.data.len() == 36 => {
+            let program_data = get_program_data_address(&address);
+            // Immutable programs have no upgrade authority to index.
+            Ok(get_upgrade_authority(ctx, &program_data)
+                .await?
+                .map(|authority| (authority, true)))
+        }
+        _ => Ok(Some((address, false))),
+    }
+}
+
+/// Explains why an index account couldn't be read, for a message that starts with its address.
+fn index_data_error(e: &ProgramError) -> &'static str {
+    if *e == IndexError::UnsupportedVersion.into() {
+        "has a layout version this CLI doesn't support; upgrade the CLI to read it"
+    } else {
+        "has unrecognized data"
+    }
+}
+
+/// Fetches the index account, returning `None` if it doesn't exist or isn't owned by the index
+/// program.
+async fn get_index_account(
+    ctx: &Context,
+    index_key: &Pubkey,
+) -> Result<Option<Account>, ClientError> {
+    Ok(ctx
+        .get_account(index_key)
+        .await?
+        .filter(|index| index.owner == ctx.program_id))
+}
+
+/// Whether an index address holds lamports but is still owned by the system program, i.e. it was
+/// funded before anyone indexed the authority. `index` completes such accounts in place.
+fn is_uninitialized_index(account: &Account) -> bool {
+    account.owner == system_program::id() && account.lamports > 0
+}
+
+/// Polls the index account until it can be read at the client's commitment, which may lag behind
+/// the confirmation of the transaction that created it, e.g. on a load-balanced RPC. Gives up
+/// after `timeout` and returns whether the account became readable. Reads require a node that has
+/// processed the slot `signature` landed in, so a lagging node can't report the index missing.
+async fn wait_until_visible(
+    ctx: &Context,
+    index_key: &Pubkey,
+    signature: &Signature,
+    timeout: Duration,
+) -> bool {
+    match ctx.client.get_signature_statuses(&[*signature]).await {
+        Ok(response) => {
+            if let Some(Some(status)) = response.value.first() {
+                ctx.raise_min_context_slot(status.slot);
+            }
+        }
+        Err(e) => debug!("Failed to fetch the slot of {}: {}", signature, e),
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        if is_indexed(ctx, index_key).await {
+            return true;
+        }
+        if Instant::now() >= deadline || interrupted() {
+            return false;
+        }
+        tokio::time::sleep(CONFIRM_POLL_INTERVAL).await;
+    }
+}
+
+/// Lowercase name of a commitment level, as accepted by `--commitment`.
+fn commitment_name(commitment: CommitmentConfig) -> String {
+    format!("{:?}", commitment.commitment).to_lowercase()
+}
+
+/// Checks whether the index account exists right now. Unlike [`get_index_account`] this always
+/// goes to the RPC, since it is polled while waiting for the index to be created.
+async fn is_indexed(ctx: &Context, index_key: &Pubkey) -> bool {
+    ctx.fetch_account(index_key)
+        .await
+        .is_ok_and(|index| index.is_some_and(|index| index.owner == ctx.program_id))
+}
+
+#[derive(Serialize)]
+struct MultisigInfo {
+    multisig: String,
+    threshold: u16,
+    total_members: usize,
+    members: Vec<String>,
+    authority: String,
+    authority_index: u32,
+    index: String,
+    indexed: bool,
+    created_slot: Option<u64>,
+    updated_slot: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct ResolvedAddresses {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    multisig: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authority_index: Option<u32>,
+    authority: String,
+    index: String,
+}
+
+/// Derives the authority and index PDAs for `address` locally. The address isn't fetched, so
+/// nothing checks that the multisig or authority actually exists.
+fn resolve(
+    program_id: &Pubkey,
+    squads_program_id: &Pubkey,
+    address: Pubkey,
+    authority_index: u32,
+    is_authority: bool,
+    output: OutputFormat,
+) -> anyhow::Result<()> {
+    let (multisig, authority_index, authority) = if is_authority {
+        (None, None, address)
+    } else {
+        let (authority, _) =
+            derive_squads_authority_with_program_id(&address, authority_index, squads_program_id);
+        (Some(address), Some(authority_index), authority)
+    };
+    let index = find_index_address(&authority, program_id).0;
+    match output {
+        OutputFormat::Text => {
+            if let (Some(multisig), Some(authority_index)) = (multisig, authority_index) {
+                println!("Multisig: {}", multisig);
+                println!("Authority index: {}", authority_index);
+            }
+            println!("Authority: {}", authority);
+            println!("Index: {}", index);
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let resolved = ResolvedAddresses {
+                multisig: multisig.map(|multisig| multisig.to_string()),
+                authority_index,
+                authority: authority.to_string(),
+                index: index.to_string(),
+            };
+            println!("{}", output.to_json(&resolved)?);
+        }
+    }
+    Ok(())
+}
+
+/// Fields of an index account as decoded by [`IndexAccount::try_from_account_data`].
+#[derive(Serialize)]
+struct DecodedIndex {
+    version: u8,
+    multisig: String,
+    authority_index: u32,
+    bump: u8,
+    created_slot: u64,
+    updated_slot: u64,
+}
+
+impl From<IndexAccount> for DecodedIndex {
+    fn from(index_account: IndexAccount) -> Self {
+        Self {
+            version: index_account.version,
+            multisig: index_account.multisig.to_string(),
+            authority_index: index_account.authority_index,
+            bump: index_account.bump,
+            created_slot: index_account.created_slot,
+            updated_slot: index_account.updated_slot,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct IndexDump {
+    index: String,
+    /// The authority the index was derived from, unless the index address was passed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authority: Option<String>,
+    owner: String,
+    lamports: u64,
+    executable: bool,
+    data_len: usize,
+    data_hex: String,
+    data_base64: String,
+    /// `None` when the data isn't a valid index account, see `decode_error`.
+    decoded: Option<DecodedIndex>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decode_error: Option<String>,
+}
+
+/// Renders `data` as lines of 16 hex bytes, each prefixed with its offset.
+fn hex_dump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let bytes = chunk
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<_>>();
+            format!("{:04x}  {}", i * 16, bytes.join(" "))
+        })
+        .collect()
+}
+
+/// Returns the index address for `address`, which is an authority unless `is_index` is set or the
+/// index program owns it, along with the authority when that is known.
+async fn resolve_index_address(
+    ctx: &Context,
+    address: Pubkey,
+    is_index: bool,
+) -> anyhow::Result<(Pubkey, Option<Pubkey>)> {
+    // An address owned by the index program can only be an index account, not an authority.
+    let is_index = is_index
+        || ctx
+            .get_account(&address)
+            .await?
+            .is_some_and(|account| account.owner == ctx.program_id);
+    Ok(match is_index {
+        true => (address, None),
+        false => (
+            find_index_address(&address, &ctx.program_id).0,
+            Some(address),
+        ),
+    })
+}
+
+/// Prints the index account at `address`, or the index of the authority at `address`, byte for
+/// byte along with whatever it decodes to. Unlike the other commands it doesn't require the
+/// account to be a valid index, so it can show what a broken or legacy account holds.
+async fn dump(ctx: &Context, address: Pubkey, is_index: bool) -> Result<Status, CliError> {
+    let (index_key, authority) = resolve_index_address(ctx, address, is_index).await?;
+    let Some(account) = ctx.get_account(&index_key).await? else {
+        return Err(CliError::NotIndexed(authority.unwrap_or(index_key)));
+    };
+
+    let (decoded, decode_error) = if account.owner == ctx.program_id && account.data.is_empty() {
+        (
+            None,
+            Some("empty account on the legacy layout, run `migrate` to convert it".to_string()),
+        )
+    } else {
+        match IndexAccount::try_from_account_data(&account.data) {
+            Ok(index_account) => (Some(DecodedIndex::from(index_account)), None),
+            Err(e) => (None, Some(index_data_error(&e).to_string())),
+        }
+    };
+    let hex = hex_dump(&account.data);
+    let report = IndexDump {
+        index: index_key.to_string(),
+        authority: authority.map(|authority| authority.to_string()),
+        owner: account.owner.to_string(),
+        lamports: account.lamports,
+        executable: account.executable,
+        data_len: account.data.len(),
+        data_hex: account
+            .data
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        data_base64: BASE64_STANDARD.encode(&account.data),
+        decoded,
+        decode_error,
+    };
+    match ctx.output {
+        OutputFormat::Text => {
+            println!("Index: {}", report.index);
+            if let Some(authority) = &report.authority {
+                println!("Authority: {}", authority);
+            }
+            let owner_note = if account.owner == ctx.program_id {
+                " (index program)"
+            } else if account.owner == system_program::id() {
+                " (system program, not initialized)"
+            } else {
+                ""
+            };
+            println!("Owner: {}{}", report.owner, owner_note);
+            println!(
+                "Lamports: {} ({} SOL)",
+                report.lamports,
+                lamports_to_sol(report.lamports)
+            );
+            println!("Executable: {}", report.executable);
+            println!("Data length: {} bytes", report.data_len);
+            if !hex.is_empty() {
+                println!();
+                for line in hex.iter() {
+                    println!("  {}", line);
+                }
+                println!();
+                println!("Base64: {}", report.data_base64);
+            }
+            println!();
+            match (&report.decoded, &report.decode_error) {
+                (Some(decoded), _) => {
+                    println!("Decoded IndexAccount {}", mark(true));
+                    println!("  Version: {}", decoded.version);
+                    println!("  Multisig: {}", decoded.multisig);
+                    println!("  Authority index: {}", decoded.authority_index);
+                    println!("  Bump: {}", decoded.bump);
+                    println!("  Created at slot: {}", decoded.created_slot);
+                    println!("  Updated at slot: {}", decoded.updated_slot);
+                }
+                (None, error) => println!(
+                    "Not a valid IndexAccount {}: {}",
+                    mark(false),
+                    error.as_deref().unwrap_or_default()
+                ),
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(Status::Success)
+}
+
+#[derive(Serialize)]
+struct IndexCreation {
+    index: String,
+    /// The authority the index was derived from, unless the index address was passed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authority: Option<String>,
+    /// The slot stored in the index account, if it still exists and decodes.
+    created_slot: Option<Slot>,
+    signature: Option<String>,
+    slot: Option<Slot>,
+    block_time: Option<i64>,
+    /// Signatures fetched for the index account, newest first.
+    transactions: usize,
+}
+
+/// Returns the transaction in `history`, ordered newest first as the RPC returns it, that created
+/// the index: the oldest successful one, in `created_slot` when that is known. An index that was
+/// closed and created again has several such transactions, and `created_slot` picks the one behind
+/// the current account. `None` means the history doesn't reach back to the creation.
+fn find_creation(
+    history: &[RpcConfirmedTransactionStatusWithSignature],
+    created_slot: Option<Slot>,
+) -> Option<&RpcConfirmedTransactionStatusWithSignature> {
+    history
+        .iter()
+        .rev()
+        .filter(|tx| tx.err.is_none())
+        .find(|tx| match created_slot {
+            Some(slot) => tx.slot == slot,
+            None => true,
+        })
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time, e.g. `2023-11-14 22:13:20 UTC`.
+fn format_unix_time(secs: i64) -> String {
+    let days = secs.div_euclid(86_400);
+    let secs_of_day = secs.rem_euclid(86_400);
+    // Converts days since 1970-01-01 to a proleptic Gregorian date, counting 400-year eras from
+    // 0000-03-01 so leap days fall at the end of each year.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Prints the signature, slot and block time of the transaction that created the index account
+/// at `address`, or the index of the authority at `address`, by paging through the index's
+/// transaction history back to its creation.
+async fn history(ctx: &Context, address: Pubkey, is_index: bool) -> Result<Status, CliError> {
+    let (index_key, authority) = resolve_index_address(ctx, address, is_index).await?;
+    let created_slot = get_index_account(ctx, &index_key)
+        .await?
+        .and_then(|account| IndexAccount::try_from_account_data(&account.data).ok())
+        .map(|index_account| index_account.created_slot);
+
+    let mut signatures: Vec<RpcConfirmedTransactionStatusWithSignature> = Vec::new();
+    loop {
+        let before = signatures
+            .last()
+            .and_then(|tx| Signature::from_str(&tx.signature).ok());
+        ctx.timings.scan_rpc_calls.fetch_add(1, Ordering::Relaxed);
+        let page = ctx
+            .with_retries("fetching index history", || {
+                let config = GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    ..GetConfirmedSignaturesForAddress2Config::default()
+                };
+                ctx.client
+                    .get_signatures_for_address_with_config(&index_key, config)
+            })
+            .await?;
+        let done = page.is_empty()
+            // The creation can't be older than the slot it recorded.
+            || created_slot.is_some_and(|slot| page.last().is_some_and(|tx| tx.slot < slot));
+        signatures.extend(page);
+        if done {
+            break;
+        }
+        if signatures.len() >= HISTORY_MAX_SIGNATURES {
+            warn!(
+                "Stopped after {} transactions for {}",
+                signatures.len(),
+                index_key
+            );
+            break;
+        }
+        debug!(
+            "Fetched {} transactions for {}",
+            signatures.len(),
+            index_key
+        );
+    }
+
+    let exists = ctx.get_account(&index_key).await?.is_some();
+    if signatures.is_empty() && !exists {
+        return Err(CliError::NotIndexed(authority.unwrap_or(index_key)));
+    }
+    let creation = find_creation(&signatures, created_slot);
+    if creation.is_none() {
+        // An account that exists was created by some transaction, so a history that doesn't
+        // reach it means the node doesn't keep it rather than that there is nothing to find.
+        warn!(
+            "The RPC endpoint returned no creation transaction for {}. It likely only keeps recent history, so use an archival RPC endpoint with --url to find it",
+            index_key
+        );
+    }
+    let report = IndexCreation {
+        index: index_key.to_string(),
+        authority: authority.map(|authority| authority.to_string()),
+        created_slot,
+        signature: creation.map(|tx| tx.signature.clone()),
+        slot: creation.map(|tx| tx.slot),
+        block_time: creation.and_then(|tx| tx.block_time),
+        transactions: signatures.len(),
+    };
+    match ctx.output {
+        OutputFormat::Text => {
+            println!("Index: {}", report.index);
+            if let Some(authority) = &report.authority {
+                println!("Authority: {}", authority);
+            }
+            if let Some(created_slot) = report.created_slot {
+                println!("Created at slot: {}", created_slot);
+            }
+            match creation {
+                Some(tx) => {
+                    println!("Creation transaction {}", mark(true));
+                    println!("  Signature: {}", tx.signature);
+                    println!("  Slot: {}", tx.slot);
+                    match tx.block_time {
+                        Some(block_time) => {
+                            println!("  Block time: {}", format_unix_time(block_time))
+                        }
+                        None => println!("  Block time: unknown"),
+                    }
+                    if let Ok(signature) = Signature::from_str(&tx.signature) {
+                        println!(
+                            "  Explorer: {}",
+                            get_explorer_url(&signature, &ctx.network_url)
+                        );
+                    }
+                }
+                None => println!(
+                    "Creation transaction {}: not in the {} transactions returned by the RPC endpoint",
+                    mark(false),
+                    report.transactions
+                ),
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(match creation {
+        Some(_) => Status::Success,
+        None => Status::RpcError,
+    })
+}
+
+async fn info(ctx: &Context, address: Pubkey) -> Result<Status, CliError> {
+    let Some(multisig) = resolve_multisig(ctx, address).await? else {
+        return Err(CliError::MultisigNotFound(address));
+    };
+    let Some(ms) = ctx
+        .get_account(&multisig)
+        .await?
+        .and_then(|account| parse_multisig(&account.data))
+    else {
+        return Err(CliError::InvalidMultisig(multisig));
+    };
+    // `index` links the authority at the default authority index, so that is the one reported.
+    let (authority_key, _) = ctx.squads_authority(&multisig, DEFAULT_AUTHORITY_INDEX);
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority_key, &program_id).0;
+    let index = get_index_account(ctx, &index_key).await?;
+    let indexed = index.is_some();
+    let index_account =
+        index.and_then(|index| IndexAccount::try_from_account_data(&index.data).ok());
+    // The index records the authority index it was created for, which has to be the one the
+    // authority was derived with.
+    let authority_index = index_account.map_or(DEFAULT_AUTHORITY_INDEX, |index_account| {
+        index_account.authority_index
+    });
+    let status = if authority_index == DEFAULT_AUTHORITY_INDEX {
+        Status::Success
+    } else {
+        warn!(
+            "Index {} records authority index {}, but {} derives from authority index {}",
+            index_key, authority_index, authority_key, DEFAULT_AUTHORITY_INDEX
+        );
+        Status::Invalid
+    };
+    let created_slot = index_account.map(|index_account| index_account.created_slot);
+    let updated_slot = index_account
+        .map(|index_account| index_account.updated_slot)
+        .filter(|slot| *slot != 0);
+
+    match ctx.output {
+        OutputFormat::Text => {
+            println!("Multisig: {}", multisig);
+            print_members(&ms);
+            println!(
+                "Authority: {} (authority index {})",
+                authority_key, authority_index
+            );
+            println!("Index: {}", index_key);
+            println!(
+                "Indexed: {} {}",
+                if indexed { "yes" } else { "no" },
+                mark(indexed)
+            );
+            if let Some(created_slot) = created_slot {
+                println!("Created at slot: {}", created_slot);
+            }
+            if let Some(updated_slot) = updated_slot {
+                println!("Updated at slot: {}", updated_slot);
+            }
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => {
+            let info = MultisigInfo {
+                multisig: multisig.to_string(),
+                threshold: ms.threshold,
+                total_members: ms.keys.len(),
+                members: ms.keys.iter().map(|k| k.to_string()).collect(),
+                authority: authority_key.to_string(),
+                authority_index,
+                index: index_key.to_string(),
+                indexed,
+                created_slot,
+                updated_slot,
+            };
+            println!("{}", ctx.output.to_json(&info)?);
+        }
+    }
+    Ok(status)
+}
+
+/// Layout version of the JSON written by `prove`.
+const PROOF_VERSION: u8 = 1;
+
+/// Evidence that a program is controlled by a Squads V3 multisig through its index, written by
+/// `prove` and checked by `verify`. Every address can be re-derived from the others, and every
+/// account read can be re-read at or after `slot`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct IndexProof {
+    version: u8,
+    /// Slot the accounts were read at or after.
+    slot: Slot,
+    program: String,
+    program_data: String,
+    upgrade_authority: String,
+    multisig: String,
+    authority_index: u32,
+    threshold: u16,
+    members: Vec<String>,
+    /// How `upgrade_authority` derives from `multisig` and `authority_index`.
+    authority_derivation: PdaDerivation,
+    /// How the index address derives from `upgrade_authority`.
+    index_derivation: PdaDerivation,
+    /// What the index account stores.
+    index: IndexEvidence,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PdaDerivation {
+    address: String,
+    program_id: String,
+    seeds: Vec<ProofSeed>,
+    bump: u8,
+}
+
+/// A PDA seed with its value, like [`SeedSchema`].
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ProofSeed {
+    Const { value: String },
+    Pubkey { value: String },
+    U32 { value: u32 },
+}
+
+impl ProofSeed {
+    fn to_bytes(&self) -> Option<Vec<u8>> {
+        Some(match self {
+            ProofSeed::Const { value } => value.as_bytes().to_vec(),
+            ProofSeed::Pubkey { value } => Pubkey::from_str(value).ok()?.to_bytes().to_vec(),
+            ProofSeed::U32 { value } => value.to_le_bytes().to_vec(),
+        })
+    }
+}
+
+impl PdaDerivation {
+    fn new(address: Pubkey, program_id: &Pubkey, seeds: Vec<ProofSeed>, bump: u8) -> Self {
+        Self {
+            address: address.to_string(),
+            program_id: program_id.to_string(),
+            seeds,
+            bump,
+        }
+    }
+
+    /// Whether the seeds and bump derive `address` under `program_id`.
+    fn derives(&self) -> bool {
+        let (Ok(address), Ok(program_id)) = (
+            Pubkey::from_str(&self.address),
+            Pubkey::from_str(&self.program_id),
+        ) else {
+            return false;
+        };
+        let Some(mut seeds) = self
+            .seeds
+            .iter()
+            .map(ProofSeed::to_bytes)
+            .collect::<Option<Vec<_>>>()
+        else {
+            return false;
+        };
+        seeds.push(vec![self.bump]);
+        let seeds = seeds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        Pubkey::create_program_address(&seeds, &program_id).is_ok_and(|derived| derived == address)
+    }
+}
+
+fn authority_seeds(multisig: &Pubkey, authority_index: u32) -> Vec<ProofSeed> {
+    vec![
+        ProofSeed::Const {
+            value: "squad".to_string(),
+        },
+        ProofSeed::Pubkey {
+            value: multisig.to_string(),
+        },
+        ProofSeed::U32 {
+            value: authority_index,
+        },
+        ProofSeed::Const {
+            value: "authority".to_string(),
+        },
+    ]
+}
+
+fn index_seeds(authority: &Pubkey) -> Vec<ProofSeed> {
+    vec![ProofSeed::Pubkey {
+        value: authority.to_string(),
+    }]
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct IndexEvidence {
+    owner: String,
+    multisig: String,
+    authority_index: u32,
+    created_slot: u64,
+}
+
+/// Collects the accounts that link `program` to its multisig into an [`IndexProof`]. Fails unless
+/// the program is upgradeable by a Squads vault whose index points back at the same multisig.
+async fn prove(ctx: &Context, program: Pubkey) -> Result<Status, CliError> {
+    let slot = ctx.client.get_slot().await?;
+    match ctx.get_account(&program).await? {
+        Some(account)
+            if account.owner == bpf_loader_upgradeable::id() && account.data.len() == 36 => {}
+        _ => {
+            return Err(CliError::Invalid(format!(
+                "{} is not an upgradeable program",
+                program
+            )))
+        }
+    }
+    let program_data = get_program_data_address(&program);
+    let Some(authority) = get_upgrade_authority(ctx, &program_data).await? else {
+        return Err(CliError::ImmutableProgram);
+    };
+    let (index_key, index_bump) = find_index_address(&authority, &ctx.program_id);
+    let Some(index) = get_index_account(ctx, &index_key).await? else {
+        return Err(CliError::NotIndexed(authority));
+    };
+    // A legacy index doesn't record its multisig, so there is nothing to prove the link with.
+    let index_account = IndexAccount::try_from_account_data(&index.data).map_err(|_| {
+        CliError::Invalid(format!(
+            "Index {} doesn't record its multisig, run `migrate` to upgrade it",
+            index_key
+        ))
+    })?;
+    let multisig = index_account.multisig;
+    let authority_index = index_account.authority_index;
+    let (derived, authority_bump) = ctx.squads_authority(&multisig, authority_index);
+    if derived != authority {
+        return Err(CliError::Invalid(format!(
+            "Index {} points at authority {} of multisig {}, which is not the upgrade authority {}",
+            index_key, authority_index, multisig, authority
+        )));
+    }
+    let Some(ms) = ctx
+        .get_account(&multisig)
+        .await?
+        .filter(|account| account.owner == ctx.squads_program_id)
+        .and_then(|account| parse_multisig(&account.data))
+    else {
+        return Err(CliError::InvalidMultisig(multisig));
+    };
+    let proof = IndexProof {
+        version: PROOF_VERSION,
+        slot,
+        program: program.to_string(),
+        program_data: program_data.to_string(),
+        upgrade_authority: authority.to_string(),
+        multisig: multisig.to_string(),
+        authority_index,
+        threshold: ms.threshold,
+        members: ms.keys.iter().map(|k| k.to_string()).collect(),
+        authority_derivation: PdaDerivation::new(
+            authority,
+            &ctx.squads_program_id,
+            authority_seeds(&multisig, authority_index),
+            authority_bump,
+        ),
+        index_derivation: PdaDerivation::new(
+            index_key,
+            &ctx.program_id,
+            index_seeds(&authority),
+            index_bump,
+        ),
+        index: IndexEvidence {
+            owner: index.owner.to_string(),
+            multisig: multisig.to_string(),
+            authority_index,
+            created_slot: index_account.created_slot,
+        },
+    };
+    println!("{}", ctx.output.to_json(&proof)?);
+    Ok(Status::Success)
+}
+
+fn read_proof(path: &Path) -> anyhow::Result<IndexProof> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path.display(), e))?;
+    let proof: IndexProof = serde_json::from_str(&contents).map_err(|e| {
+        anyhow!(
+            "{} is not a proof written by `prove`: {}",
+            path.display(),
+            e
+        )
+    })?;
+    if proof.version != PROOF_VERSION {
+        return Err(anyhow!(
+            "{} has proof version {}, this build reads version {}",
+            path.display(),
+            proof.version,
+            PROOF_VERSION
+        ));
+    }
+    Ok(proof)
+}
+
+/// Checks that the addresses in `proof` derive from each other under the given program ids,
+/// without any RPC calls. The seeds are compared with the ones the programs use, so a proof
+/// can't pass with a derivation of its own.
+fn verify_proof_offline(
+    proof: &IndexProof,
+    program_id: &Pubkey,
+    squads_program_id: &Pubkey,
+) -> Vec<DoctorCheck> {
+    let pubkey = |address: &str| Pubkey::from_str(address).ok();
+    let mut checks = vec![];
+    let program_data = pubkey(&proof.program).map(|program| get_program_data_address(&program));
+    checks.push(DoctorCheck::new(
+        "Program data",
+        program_data.is_some_and(|program_data| program_data.to_string() == proof.program_data),
+        format!(
+            "{} is the program data account of {}",
+            proof.program_data, proof.program
+        ),
+    ));
+    let authority_seeds =
+        pubkey(&proof.multisig).map(|multisig| authority_seeds(&multisig, proof.authority_index));
+    let authority = &proof.authority_derivation;
+    checks.push(DoctorCheck::new(
+        "Authority derivation",
+        authority.address == proof.upgrade_authority
+            && authority.program_id == squads_program_id.to_string()
+            && authority_seeds.as_ref() == Some(&authority.seeds)
+            && authority.derives(),
+        format!(
+            "{} is authority {} of multisig {}",
+            proof.upgrade_authority, proof.authority_index, proof.multisig
+        ),
+    ));
+    let index_seeds = pubkey(&proof.upgrade_authority).map(|authority| index_seeds(&authority));
+    let index = &proof.index_derivation;
+    checks.push(DoctorCheck::new(
+        "Index derivation",
+        index.program_id == program_id.to_string()
+            && index_seeds.as_ref() == Some(&index.seeds)
+            && index.derives(),
+        format!(
+            "{} is the index of {}",
+            index.address, proof.upgrade_authority
+        ),
+    ));
+    checks.push(DoctorCheck::new(
+        "Index contents",
+        proof.index.owner == program_id.to_string()
+            && proof.index.multisig == proof.multisig
+            && proof.index.authority_index == proof.authority_index,
+        format!(
+            "the index points at authority {} of multisig {}",
+            proof.index.authority_index, proof.index.multisig
+        ),
+    ));
+    checks.push(DoctorCheck::new(
+        "Threshold",
+        proof.threshold > 0 && usize::from(proof.threshold) <= proof.members.len(),
+        format!("{} of {} members", proof.threshold, proof.members.len()),
+    ));
+    checks
+}
+
+/// Re-reads the accounts in `proof` and compares them with what it claims, on top of
+/// [`verify_proof_offline`].
+async fn verify(ctx: &Context, path: &Path) -> Result<Status, CliError> {
+    let proof = read_proof(path)?;
+    let mut checks = verify_proof_offline(&proof, &ctx.program_id, &ctx.squads_program_id);
+    let invalid = |field: &str| CliError::Invalid(format!("Invalid {} in proof", field));
+    let program_data =
+        Pubkey::from_str(&proof.program_data).map_err(|_| invalid("program_data"))?;
+    let multisig = Pubkey::from_str(&proof.multisig).map_err(|_| invalid("multisig"))?;
+    let index_key =
+        Pubkey::from_str(&proof.index_derivation.address).map_err(|_| invalid("index address"))?;
+
+    let authority = get_upgrade_authority(ctx, &program_data).await?;
+    checks.push(DoctorCheck::new(
+        "On-chain upgrade authority",
+        authority.is_some_and(|authority| authority.to_string() == proof.upgrade_authority),
+        match authority {
+            Some(authority) => format!("{} can upgrade {}", authority, proof.program),
+            None => format!("{} is immutable", proof.program),
+        },
+    ));
+    let ms = ctx
+        .get_account(&multisig)
+        .await?
+        .filter(|account| account.owner == ctx.squads_program_id)
+        .and_then(|account| parse_multisig(&account.data));
+    let members = ms
+        .as_ref()
+        .map(|ms| ms.keys.iter().map(|k| k.to_string()).collect::<Vec<_>>());
+    checks.push(DoctorCheck::new(
+        "On-chain multisig",
+        ms.as_ref()
+            .is_some_and(|ms| ms.threshold == proof.threshold)
+            && members.as_ref() == Some(&proof.members),
+        match &ms {
+            Some(ms) => format!("{} of {} members", ms.threshold, ms.keys.len()),
+            None => format!("{} is not a valid multisig", multisig),
+        },
+    ));
+    let index = get_index_account(ctx, &index_key).await?;
+    let index_account =
+        index.and_then(|index| IndexAccount::try_from_account_data(&index.data).ok());
+    checks.push(DoctorCheck::new(
+        "On-chain index",
+        index_account.is_some_and(|index_account| {
+            index_account.multisig == multisig
+                && index_account.authority_index == proof.authority_index
+        }),
+        match index_account {
+            Some(index_account) => format!(
+                "{} points at authority {} of multisig {}",
+                index_key, index_account.authority_index, index_account.multisig
+            ),
+            None => format!("{} does not exist", index_key),
+        },
+    ));
+    Ok(print_doctor_report(&checks, ctx.output)?)
+}
+
+#[derive(Serialize)]
+struct IndexListing {
+    index: String,
+    authority: String,
+    multisig: String,
+    authority_index: u32,
+    created_slot: u64,
+    threshold: Option<u16>,
+    total_members: Option<usize>,
+}
+
+impl IndexListing {
+    fn print(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "Index: {}", self.index)?;
+        writeln!(out, "  Authority: {}", self.authority)?;
+        writeln!(
+            out,
+            "  Multisig: {} (authority index {})",
+            self.multisig, self.authority_index
+        )?;
+        match (self.threshold, self.total_members) {
+            (Some(threshold), Some(total_members)) => {
+                writeln!(out, "  Threshold: {}/{}", threshold, total_members)?
+            }
+            _ => writeln!(
+                out,
+                "  Multisig account is missing or invalid {}",
+                mark(false)
+            )?,
+        }
+        writeln!(out, "  Created at slot: {}", self.created_slot)
+    }
+}
+
+/// Lists index accounts with a `getProgramAccounts` memcmp filter, optionally restricted to the
+/// ones pointing at `multisig`. Legacy index accounts don't store a multisig and are not listed.
+async fn list(ctx: &Context, multisig: Option<Pubkey>) -> Result<Status, CliError> {
+    let client = &ctx.client;
+    let program_id = ctx.program_id;
+    let filters = squads_v3_index::index_memcmp_filters(multisig.as_ref())
+        .into_iter()
+        .map(|(offset, bytes)| RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, &bytes)))
+        .collect();
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let indexes = ctx
+        .with_retries("index accounts", || {
+            client.get_program_accounts_with_config(&program_id, config.clone())
+        })
+        .await?
+        .into_iter()
+        .filter_map(|(index_key, account)| {
+            match IndexAccount::try_from_account_data(&account.data) {
+                Ok(index_account) => Some((index_key, index_account)),
+                Err(e) => {
+                    warn!("Index account {} {}", index_key, index_data_error(&e));
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    if indexes.is_empty() && ctx.output == OutputFormat::Text {
+        println!("No index accounts found");
+    }
+
+    // Multisigs are fetched one bulk request at a time and each page is printed before the next
+    // is fetched, so text and `jsonl` output stream instead of waiting for every account.
+    let mut listings = vec![];
+    let mut stdout = std::io::stdout().lock();
+    for page in indexes.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        let multisig_keys = page
+            .iter()
+            .map(|(_, index_account)| index_account.multisig)
+            .collect::<Vec<_>>();
+        let multisigs = ctx.get_multiple_accounts(&multisig_keys).await?;
+        for ((index_key, index_account), multisig) in page.iter().zip(multisigs) {
+            let ms = multisig.and_then(|account| parse_multisig(&account.data));
+            // The index PDA is derived from the authority, which is in turn derived from the
+            // multisig and authority index stored in the account.
+            let (authority_key, _) =
+                ctx.squads_authority(&index_account.multisig, index_account.authority_index);
+            let listing = IndexListing {
+                index: index_key.to_string(),
+                authority: authority_key.to_string(),
+                multisig: index_account.multisig.to_string(),
+                authority_index: index_account.authority_index,
+                created_slot: index_account.created_slot,
+                threshold: ms.as_ref().map(|ms| ms.threshold),
+                total_members: ms.as_ref().map(|ms| ms.keys.len()),
+            };
+            match ctx.output {
+                OutputFormat::Text => listing.print(&mut stdout)?,
+                OutputFormat::Json => listings.push(listing),
+                OutputFormat::Jsonl => writeln!(stdout, "{}", serde_json::to_string(&listing)?)?,
+            }
+        }
+        stdout.flush()?;
+    }
+    if ctx.output == OutputFormat::Json {
+        writeln!(stdout, "{}", serde_json::to_string_pretty(&listings)?)?;
+    }
+    Ok(Status::Success)
+}
+
+/// Why an index account no longer links an authority to a live multisig.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum OrphanReason {
+    /// The multisig stored in the index was closed.
+    MultisigMissing,
+    /// The account at the stored multisig address isn't a Squads V3 multisig.
+    NotAMultisig,
+    /// The stored multisig and authority index don't derive the index address, e.g. because the
+    /// multisig belongs to another Squads deployment.
+    DerivationMismatch,
+    /// The authority no longer upgrades any program, set by `--programs`.
+    NoProgram,
+}
+
+impl OrphanReason {
+    fn description(self) -> &'static str {
+        match self {
+            OrphanReason::MultisigMissing => "the multisig account no longer exists",
+            OrphanReason::NotAMultisig => "the multisig account is not a Squads V3 multisig",
+            OrphanReason::DerivationMismatch => {
+                "the stored multisig and authority index don't derive this index"
+            }
+            OrphanReason::NoProgram => "the authority no longer upgrades any program",
+        }
+    }
+
+    /// Whether the authority can still sign the close, through a transaction of its multisig.
+    fn closeable(self) -> bool {
+        self == OrphanReason::NoProgram
+    }
+}
+
+#[derive(Serialize)]
+struct OrphanIndex {
+    index: String,
+    authority: String,
+    multisig: String,
+    authority_index: u32,
+    lamports: u64,
+    reason: OrphanReason,
+    /// The close instruction for the multisig to execute, set by `--close` for orphans whose
+    /// authority can still sign.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    close_instruction: Option<EncodedInstruction>,
+    #[serde(skip)]
+    close_ix: Option<Instruction>,
+}
+
+impl OrphanIndex {
+    fn print(&self, close: bool) {
+        println!(
+            "Index: {} ({} SOL)",
+            self.index,
+            lamports_to_sol(self.lamports)
+        );
+        println!(
+            "  Authority: {} (authority index {} of multisig {})",
+            self.authority, self.authority_index, self.multisig
+        );
+        println!("  Orphaned: {} {}", self.reason.description(), mark(false));
+        match (&self.close_ix, &self.close_instruction) {
+            (Some(ix), Some(instruction)) => {
+                println!(
+                    "  Propose this instruction as a transaction of multisig {}:",
+                    self.multisig
+                );
+                print!("{}", describe_instruction(ix));
+                println!("Data (base58): {}", instruction.data);
+            }
+            _ if close => println!("  Can't be closed, its authority can no longer sign"),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct OrphanReport {
+    scanned: usize,
+    orphans: Vec<OrphanIndex>,
+    /// Rent held by the orphans.
+    lamports: u64,
+}
+
+/// Checks an index account against the authority derived from its stored multisig and authority
+/// index, and against that multisig's account. `None` means it still links a live multisig.
+fn orphan_reason(
+    program_id: &Pubkey,
+    squads_program_id: &Pubkey,
+    index_key: &Pubkey,
+    authority: &Pubkey,
+    multisig: Option<&Account>,
+) -> Option<OrphanReason> {
+    if find_index_address(authority, program_id).0 != *index_key {
+        return Some(OrphanReason::DerivationMismatch);
+    }
+    match multisig {
+        None => Some(OrphanReason::MultisigMissing),
+        Some(account)
+            if account.owner != *squads_program_id || parse_multisig(&account.data).is_none() =>
+        {
+            Some(OrphanReason::NotAMultisig)
+        }
+        Some(_) => None,
+    }
+}
+
+/// Whether `authority` is the upgrade authority of any program, found with a `getProgramAccounts`
+/// memcmp filter on the upgradeable loader's ProgramData accounts.
+async fn upgrades_any_program(ctx: &Context, authority: &Pubkey) -> Result<bool, ClientError> {
+    // ProgramData layout: 4 byte enum tag 3, 8 byte slot, then the `Option<Pubkey>` authority.
+    let filters = vec![
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(0, &[3, 0, 0, 0])),
+        RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            12,
+            &[&[1], authority.as_ref()].concat(),
+        )),
+    ];
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            data_slice: Some(UiDataSliceConfig {
+                offset: 0,
+                length: 0,
+            }),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let loader = bpf_loader_upgradeable::id();
+    let programs = ctx
+        .with_retries(&format!("programs of {}", authority), || {
+            ctx.client
+                .get_program_accounts_with_config(&loader, config.clone())
+        })
+        .await?;
+    Ok(!programs.is_empty())
+}
+
+/// Lists the index accounts whose multisig is gone or doesn't derive them, and with `programs`
+/// the ones whose authority upgrades no program anymore. With `close`, prepares the close
+/// instruction of each orphan whose authority can still sign, refunding its rent to the given
+/// account or the payer.
+async fn scan_orphans(
+    ctx: &Context,
+    programs: bool,
+    close: Option<Option<Pubkey>>,
+) -> Result<Status, CliError> {
+    let filters = squads_v3_index::index_memcmp_filters(None)
+        .into_iter()
+        .map(|(offset, bytes)| RpcFilterType::Memcmp(Memcmp::new_base58_encoded(offset, &bytes)))
+        .collect();
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..RpcAccountInfoConfig::default()
+        },
+        ..RpcProgramAccountsConfig::default()
+    };
+    let indexes = ctx
+        .with_retries("index accounts", || {
+            ctx.client
+                .get_program_accounts_with_config(&ctx.program_id, config.clone())
+        })
+        .await?
+        .into_iter()
+        .filter_map(|(index_key, account)| {
+            match IndexAccount::try_from_account_data(&account.data) {
+                Ok(index_account) => Some((index_key, account.lamports, index_account)),
+                Err(e) => {
+                    warn!("Index account {} {}", index_key, index_data_error(&e));
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    let multisig_keys = indexes
+        .iter()
+        .map(|(_, _, index_account)| index_account.multisig)
+        .collect::<Vec<_>>();
+    let multisigs = ctx.get_multiple_accounts(&multisig_keys).await?;
+
+    let refund = close.map(|refund| refund.unwrap_or_else(|| ctx.payer.pubkey()));
+    let mut orphans = vec![];
+    for ((index_key, lamports, index_account), multisig) in indexes.iter().zip(multisigs) {
+        let (authority, _) =
+            ctx.squads_authority(&index_account.multisig, index_account.authority_index);
+        let reason = match orphan_reason(
+            &ctx.program_id,
+            &ctx.squads_program_id,
+            index_key,
+            &authority,
+            multisig.as_ref(),
+        ) {
+            Some(reason) => reason,
+            None if programs && !upgrades_any_program(ctx, &authority).await? => {
+                OrphanReason::NoProgram
+            }
+            None => continue,
+        };
+        let close_ix = refund
+            .filter(|_| reason.closeable())
+            .map(|refund| close_instruction(ctx.program_id, &authority, &refund));
+        orphans.push(OrphanIndex {
+            index: index_key.to_string(),
+            authority: authority.to_string(),
+            multisig: index_account.multisig.to_string(),
+            authority_index: index_account.authority_index,
+            lamports: *lamports,
+            reason,
+            close_instruction: close_ix.as_ref().map(EncodedInstruction::from),
+            close_ix,
+        });
+    }
+    let report = OrphanReport {
+        scanned: indexes.len(),
+        lamports: orphans.iter().map(|orphan| orphan.lamports).sum(),
+        orphans,
+    };
+    match ctx.output {
+        OutputFormat::Text => {
+            for orphan in report.orphans.iter() {
+                orphan.print(close.is_some());
+            }
+            println!(
+                "Found {} orphaned of {} index accounts, holding {} SOL",
+                report.orphans.len(),
+                report.scanned,
+                lamports_to_sol(report.lamports)
+            );
+        }
+        OutputFormat::Json | OutputFormat::Jsonl => println!("{}", ctx.output.to_json(&report)?),
+    }
+    Ok(Status::Success)
+}
+
+#[derive(Serialize)]
+struct Schema {
+    program_id: String,
+    account: AccountSchema,
+    instructions: Vec<InstructionSchema>,
+    pdas: Vec<PdaSchema>,
+    errors: Vec<ErrorSchema>,
+}
+
+#[derive(Serialize)]
+struct AccountSchema {
+    name: &'static str,
+    version: u8,
+    size: usize,
+    discriminator: [u8; 8],
+    fields: Vec<FieldSchema>,
+}
+
+#[derive(Serialize)]
+struct FieldSchema {
+    name: &'static str,
+    offset: usize,
+    size: usize,
+    #[serde(rename = "type")]
+    ty: &'static str,
+    description: &'static str,
+}
+
+#[derive(Serialize)]
+struct InstructionSchema {
+    name: &'static str,
+    /// Instruction data up to the first argument.
+    data: Vec<u8>,
+    /// Arguments following `data`, with offsets from the start of the instruction data.
+    args: Vec<FieldSchema>,
+    accounts: Vec<AccountMetaSchema>,
+}
+
+#[derive(Serialize)]
+struct AccountMetaSchema {
+    name: &'static str,
+    signer: bool,
+    writable: bool,
+    optional: bool,
+}
+
+#[derive(Serialize)]
+struct PdaSchema {
+    name: &'static str,
+    program_id: String,
+    seeds: Vec<SeedSchema>,
+}
+
+/// A PDA seed: a UTF-8 constant, a pubkey, or a little endian `u32`.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum SeedSchema {
+    Const { value: &'static str },
+    Pubkey { name: &'static str },
+    U32 { name: &'static str },
+}
+
+#[derive(Serialize)]
+struct ErrorSchema {
+    code: u32,
+    name: &'static str,
+    description: &'static str,
+}
+
+/// Describes the on-chain format of the index program deployed at `program_id`, so off-chain
+/// clients can decode index accounts and build instructions without the Rust crate. Integers are
+/// little endian and all offsets are in bytes from the start of the account data.
+fn schema(program_id: &Pubkey, squads_program_id: &Pubkey) -> Schema {
+    let mut offset = 0;
+    let mut field = |name, size, ty, description| {
+        let field = FieldSchema {
+            name,
+            offset,
+            size,
+            ty,
+            description,
+        };
+        offset += size;
+        field
+    };
+    let fields = vec![
+        field(
+            "discriminator",
+            8,
+            "[u8; 8]",
+            "Always equal to the account discriminator",
+        ),
+        field("version", 1, "u8", "Layout version"),
+        field(
+            "multisig",
+            32,
+            "pubkey",
+            "The Squads V3 multisig the authority belongs to",
+        ),
+        field(
+            "authority_index",
+            4,
+            "u32",
+            "Squads authority index the authority derives from",
+        ),
+        field("bump", 1, "u8", "Canonical bump seed of the index PDA"),
+        field("created_slot", 8, "u64", "Slot the index was created in"),
+        field(
+            "updated_slot",
+            8,
+            "u64",
+            "Last UpdateIndex slot, 0 if never updated",
+        ),
+    ];
+    debug_assert_eq!(offset, INDEX_ACCOUNT_SIZE);
+
+    let meta = |name, signer, writable, optional| AccountMetaSchema {
+        name,
+        signer,
+        writable,
+        optional,
+    };
+    let index_metas = || {
+        vec![
+            meta("system_program", false, false, false),
+            meta("authority", false, false, false),
+            meta("multisig", false, false, false),
+            meta("payer", true, true, false),
+            meta("index", false, true, false),
+            meta("rent_payer", true, true, true),
+        ]
+    };
+    let close_metas = vec![
+        meta("authority", true, false, false),
+        meta("index", false, true, false),
+        meta("recipient", false, true, false),
+    ];
+    // The names are listed by hand, so make sure they stay at the program's account positions.
+    for (position, name) in [
+        (index_accounts::SYSTEM_PROGRAM, "system_program"),
+        (index_accounts::AUTHORITY, "authority"),
+        (index_accounts::MULTISIG, "multisig"),
+        (index_accounts::PAYER, "payer"),
+        (index_accounts::INDEX, "index"),
+        (index_accounts::RENT_PAYER, "rent_payer"),
+    ] {
+        debug_assert_eq!(index_metas()[position].name, name);
+    }
+    for (position, name) in [
+        (close_accounts::AUTHORITY, "authority"),
+        (close_accounts::INDEX, "index"),
+        (close_accounts::RECIPIENT, "recipient"),
+    ] {
+        debug_assert_eq!(close_metas[position].name, name);
+    }
+    let instructions = vec![
+        InstructionSchema {
+            name: "CreateIndex",
+            data: IndexInstruction::CreateIndex.pack(),
+            args: vec![],
+            accounts: index_metas(),
+        },
+        InstructionSchema {
+            name: "CreateVaultIndex",
+            data: IndexInstruction::CreateVaultIndex { authority_index: 0 }.pack()[..1].to_vec(),
+            args: vec![FieldSchema {
+                name: "authority_index",
+                offset: 1,
+                size: 4,
+                ty: "u32",
+                description: "Squads authority index of the vault to index",
+            }],
+            accounts: index_metas(),
+        },
+        InstructionSchema {
+            name: "UpdateIndex",
+            data: IndexInstruction::UpdateIndex.pack(),
+            args: vec![],
+            accounts: index_metas(),
+        },
+        InstructionSchema {
+            name: "MigrateIndex",
+            data: IndexInstruction::MigrateIndex.pack(),
+            args: vec![],
+            accounts: index_metas(),
+        },
+        InstructionSchema {
+            name: "CloseIndex",
+            data: IndexInstruction::CloseIndex.pack(),
+            args: vec![],
+            accounts: close_metas,
+        },
+    ];
+
+    Schema {
+        program_id: program_id.to_string(),
+        account: AccountSchema {
+            name: "IndexAccount",
+            version: INDEX_ACCOUNT_VERSION,
+            size: INDEX_ACCOUNT_SIZE,
+            discriminator: IndexAccount::DISCRIMINATOR,
+            fields,
+        },
+        instructions,
+        pdas: vec![
+            PdaSchema {
+                name: "index",
+                program_id: program_id.to_string(),
+                seeds: vec![SeedSchema::Pubkey { name: "authority" }],
+            },
+            PdaSchema {
+                name: "authority",
+                program_id: squads_program_id.to_string(),
+                seeds: vec![
+                    SeedSchema::Const { value: "squad" },
+                    SeedSchema::Pubkey { name: "multisig" },
+                    SeedSchema::U32 {
+                        name: "authority_index",
+                    },
+                    SeedSchema::Const { value: "authority" },
+                ],
+            },
+        ],
+        errors: vec![
+            ErrorSchema {
+                code: IndexError::InvalidThreshold as u32,
+                name: "InvalidThreshold",
+                description: "The multisig threshold is zero or larger than its member count",
+            },
+            ErrorSchema {
+                code: IndexError::UnsupportedVersion as u32,
+                name: "UnsupportedVersion",
+                description: "The index account has a layout version this build can't read",
+            },
+            ErrorSchema {
+                code: IndexError::ExecutableIndex as u32,
+                name: "ExecutableIndex",
+                description: "The account passed as the index is executable",
+            },
+            ErrorSchema {
+                code: IndexError::IllegalIndexOwner as u32,
+                name: "IllegalIndexOwner",
+                description: "The account passed as the index to create is owned by neither the system program nor the index program",
+            },
+        ],
+    }
+}
+
+fn get_program_data_address(program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[program.as_ref()], &bpf_loader_upgradeable::id()).0
+}
+
+/// Reads the upgrade authority from a ProgramData account. Returns `None` if the program is
+/// immutable, in which case there is no authority to extract.
+async fn get_upgrade_authority(
+    ctx: &Context,
+    program_data: &Pubkey,
+) -> anyhow::Result<Option<Pubkey>> {
+    let program_data_account = ctx
+        .get_account(program_data)
+        .await?
+        .ok_or_else(|| anyhow!("Program data account {} does not exist", program_data))?;
+    // ProgramData layout: 4 byte enum tag, 8 byte slot, then the `Option<Pubkey>` authority.
+    let malformed = || anyhow!("Program data account {} is malformed", program_data);
+    match program_data_account.data.get(12) {
+        Some(0) => return Ok(None),
+        Some(_) => {}
+        None => return Err(malformed()),
+    }
+    let authority = program_data_account
+        .data
+        .get(13..45)
+        .ok_or_else(malformed)?;
+    Ok(Some(Pubkey::try_from_slice(authority)?))
+}
+
+/// Resolves a multisig, multisig authority, or program address to its Squads V3 multisig.
+async fn resolve_multisig(ctx: &Context, address: Pubkey) -> anyhow::Result<Option<Pubkey>> {
+    let authority = match ctx.get_account(&address).await? {
+        Some(account) if account.owner == ctx.squads_program_id => return Ok(Some(address)),
+        Some(account)
+            if account.owner == bpf_loader_upgradeable::id() && account.data.len() == 36 =>
+        {
+            let program_data = get_program_data_address(&address);
+            let Some(authority) = get_upgrade_authority(ctx, &program_data).await? else {
+                error!("Program is immutable");
+                return Ok(None);
+            };
+            authority
+        }
+        _ => address,
+    };
+    if authority.is_on_curve() {
+        error!(
+            "Authority {} is not a Program Derived Address {}",
+            authority,
+            mark(false)
+        );
+        return Ok(None);
+    }
+    let program_id = ctx.program_id;
+    let index_key = find_index_address(&authority, &program_id).0;
+    if let Some(multisig) = get_multisig_account_from_key(ctx, &index_key, &authority, true).await {
+        return Ok(Some(multisig));
+    }
+    Ok(get_multisig_account_from_key(ctx, &authority, &authority, true).await)
+}
+
+/// Scans the successful transactions of `key` for the multisig that derives `authority` as its
+/// vault 1. See [`find_authority_in_history`].
+async fn get_multisig_account_from_key(
+    ctx: &Context,
+    key: &Pubkey,
+    authority: &Pubkey,
+    reverse: bool,
+) -> Option<Pubkey> {
+    match find_authority_in_history(ctx, key, authority, reverse).await? {
+        (multisig, 1) => Some(multisig),
+        (multisig, authority_index) => {
+            info!(
+                "{} is not vault 1 of any multisig, but vault {} of multisig {}",
+                authority, authority_index, multisig
+            );
+            None
+        }
+    }
+}
+
+/// Scans the successful transactions of `key` for one that derives `authority` from a multisig,
+/// newest first or, with `reverse`, oldest first, and returns the multisig and the authority
+/// index it derives `authority` at. Every transaction is checked in order until a match is found,
+/// up to `SCAN_MAX_TRANSACTIONS`.
+async fn find_authority_in_history(
+    ctx: &Context,
+    key: &Pubkey,
+    authority: &Pubkey,
+    reverse: bool,
+) -> Option<(Pubkey, u32)> {
+    let config = GetConfirmedSignaturesForAddress2Config {
+        before: ctx.history_before,
+        until: match ctx.history_since {
+            Some(HistoryStart::Signature(signature)) => Some(signature),
+            _ => None,
+        },
+        ..GetConfirmedSignaturesForAddress2Config::default()
+    };
+    let since_slot = match ctx.history_since {
+        Some(HistoryStart::Slot(slot)) => slot,
+        _ => 0,
+    };
+    let _timer = ctx.timings.start(Phase::Scan);
+    let started = Instant::now();
+    ctx.timings.scan_rpc_calls.fetch_add(1, Ordering::Relaxed);
+    let signatures = match ctx
+        .client
+        .get_signatures_for_address_with_config(key, config)
+        .await
+    {
+        Ok(signatures) => signatures,
+        Err(e) => {
+            warn!("Failed to fetch transaction history for {}: {}", key, e);
+            return None;
+        }
+    };
+    if signatures.is_empty() {
+        // An account that exists was created by some transaction, so an empty history means the
+        // node doesn't keep it rather than that there is nothing to find.
+        let bounded = ctx.history_before.is_some() || ctx.history_since.is_some();
+        if !bounded && matches!(ctx.get_account(key).await, Ok(Some(_))) {
+            warn!(
+                "The RPC endpoint returned no transaction history for {}. It likely only keeps recent history, so use an archival RPC endpoint with --url to find the multisig",
+                key
+            );
+        } else {
+            debug!("No transaction history for {}", key);
+        }
+        return None;
+    }
+    let mut transaction_history = signatures
+        .iter()
+        .filter_map(|tx| {
+            if tx.err.is_none() && tx.slot >= since_slot {
+                Some(tx.clone())
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    if reverse {
+        transaction_history.reverse();
+    }
+    if transaction_history.len() > SCAN_MAX_TRANSACTIONS {
+        debug!(
+            "Only scanning the first {} of {} transactions for {}",
+            SCAN_MAX_TRANSACTIONS,
+            transaction_history.len(),
+            key
+        );
+        transaction_history.truncate(SCAN_MAX_TRANSACTIONS);
+    }
+
+    let total_transactions = transaction_history.len();
+    debug!("Scanning {} transactions for {}", total_transactions, key);
+    let progress_bar = if log_enabled!(Level::Info) {
+        ProgressBar::new(total_transactions as u64)
+    } else {
+        ProgressBar::hidden()
+    };
+    progress_bar.set_style(if plain_output() {
+        ProgressStyle::default_bar()
+            .template("{spinner} [{pos}/{len}] {wide_msg}")
+            .tick_chars("|/-\\ ")
+    } else {
+        ProgressStyle::default_bar().template("{spinner:.green} [{pos}/{len}] {wide_msg}")
+    });
+    progress_bar.enable_steady_tick(100);
+    progress_bar.set_message("Searching transaction history");
+
+    // Fetch a bounded number of transactions concurrently, but consume the results in history
+    // order so the first match is the same one a sequential scan would find.
+    let mut results = futures_util::stream::iter(transaction_history.iter())
+        .map(|tx| {
+            let progress_bar = &progress_bar;
+            async move {
+                let sig = Signature::from_str(&tx.signature).unwrap();
+                trace!("Scanning transaction {}", sig);
+                progress_bar.set_message(format!("Searching transaction history: {}", sig));
+                scan_transaction(ctx, &sig, authority).await
+            }
+        })
+        .buffered(SCAN_CONCURRENCY)
+        .enumerate();
+    let mut undecodable = 0;
+    let mut unavailable = 0;
+    while let Some((i, scan)) = results.next().await {
+        if interrupted() {
+            progress_bar.finish_and_clear();
+            warn!(
+                "Stopped scanning {} after {} of {} transactions",
+                key, i, total_transactions
+            );
+            return None;
+        }
+        progress_bar.inc(1);
+        ctx.metrics
+            .transactions_scanned
+            .fetch_add(1, Ordering::Relaxed);
+        match scan {
+            TransactionScan::Match(key) => {
+                ctx.metrics.matches_found.fetch_add(1, Ordering::Relaxed);
+                progress_bar.finish_with_message(format!(
+                    "Found multisig key after {} transactions in {:.1}s",
+                    i + 1,
+                    started.elapsed().as_secs_f64()
+                ));
+                return Some((key, 1));
+            }
+            // Each authority derives from a single multisig and index, so this is conclusive too.
+            TransactionScan::OtherVault(key, authority_index) => {
+                progress_bar.finish_with_message(format!(
+                    "Found multisig key at vault {} after {} transactions in {:.1}s",
+                    authority_index,
+                    i + 1,
+                    started.elapsed().as_secs_f64()
+                ));
+                return Some((key, authority_index));
+            }
+            TransactionScan::NoMatch => {}
+            TransactionScan::Undecodable => undecodable += 1,
+            TransactionScan::Unavailable => unavailable += 1,
+        }
+    }
+    progress_bar.finish_and_clear();
+    // Transactions that couldn't be inspected may still hold the multisig, so a miss is only
+    // conclusive when there are none.
+    if undecodable + unavailable > 0 {
+        warn!(
+            "Multisig not found in {} transactions for {} in {:.1}s: {} could not be decoded and {} could not be fetched",
+            total_transactions,
+            key,
+            started.elapsed().as_secs_f64(),
+            undecodable,
+            unavailable
+        );
+    } else {
+        debug!(
+            "Multisig not found in {} transactions for {} in {:.1}s",
+            total_transactions,
+            key,
+            started.elapsed().as_secs_f64()
+        );
+    }
+    None
+}
+
+/// Result of looking for the multisig in a single transaction.
+#[derive(Debug, PartialEq, Eq)]
+enum TransactionScan {
+    /// The transaction references the multisig the authority is derived from.
+    Match(Pubkey),
+    /// The transaction references a multisig that derives the authority at this authority index
+    /// instead of 1.
+    OtherVault(Pubkey, u32),
+    /// The transaction was inspected and doesn't reference the multisig.
+    NoMatch,
+    /// The transaction was fetched but couldn't be decoded, so it wasn't inspected.
+    Undecodable,
+    /// The transaction couldn't be fetched.
+    Unavailable,
+}
+
+/// Looks for the multisig in a single transaction, backing off and retrying when the RPC
+/// rate-limits us or the request fails in transit instead of treating that as a miss.
+async fn scan_transaction(
+    ctx: &Context,
+    signature: &Signature,
+    authority: &Pubkey,
+) -> TransactionScan {
+    let mut backoff = SCAN_INITIAL_BACKOFF;
+    let mut timed_out = false;
+    for attempt in 0..SCAN_MAX_RETRIES {
+        if attempt > 0 {
+            ctx.metrics.retries.fetch_add(1, Ordering::Relaxed);
+        }
+        ctx.timings.scan_rpc_calls.fetch_add(1, Ordering::Relaxed);
+        match extract_multisig_key_from_transaction(
+            &ctx.client,
+            signature,
+            authority,
+            &ctx.squads_program_id,
+        )
+        .await
+        {
+            Ok(scan) => return scan,
+            Err(e) => {
+                timed_out = is_timeout_error(&e);
+                debug!("Retrying transaction {} in {:?}: {}", signature, backoff, e);
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+    }
+    warn!(
+        "Giving up on transaction {} after {} attempts{}",
+        signature,
+        SCAN_MAX_RETRIES,
+        if timed_out { ": request timed out" } else { "" }
+    );
+    TransactionScan::Unavailable
+}
+
+/// Returns true if `error` is a request that exceeded the client's `--timeout`.
+fn is_timeout_error(error: &ClientError) -> bool {
+    matches!(error.kind(), ClientErrorKind::Reqwest(e) if e.is_timeout())
+}
+
+/// Returns true if the RPC endpoint answered with HTTP 429 Too Many Requests.
+fn is_rate_limited(error: &ClientError) -> bool {
+    matches!(error.kind(), ClientErrorKind::Reqwest(e) if e.status().is_some_and(|status| status.as_u16() == 429))
+}
+
+/// Returns true for rate limits and transport failures, which are worth retrying, as opposed to
+/// errors about the request itself.
+fn is_transient_rpc_error(error: &ClientError) -> bool {
+    match error.kind() {
+        ClientErrorKind::Reqwest(e) => match e.status() {
+            // No status means the request never completed.
+            None => true,
+            Some(status) => status.as_u16() == 429 || status.is_server_error(),
+        },
+        ClientErrorKind::Io(_) => true,
+        // A node behind `--min-context-slot` catches up on its own.
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED
+        }
+        _ => false,
+    }
+}
+
+/// Looks for the multisig whose authority appears in the transaction. Returns an error if the
+/// transaction couldn't be fetched because of a transient RPC failure, which is worth retrying.
+async fn extract_multisig_key_from_transaction(
+    client: &RpcClient,
+    signature: &Signature,
+    authority: &Pubkey,
+    squads_program_id: &Pubkey,
+) -> Result<TransactionScan, ClientError> {
+    let transaction_details = match client
+        .get_transaction_with_config(
+            signature,
+            RpcTransactionConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+                max_supported_transaction_version: Some(0),
+                encoding: Some(UiTransactionEncoding::Base64),
+            },
+        )
+        .await
+    {
+        Ok(transaction_details) => transaction_details,
+        Err(e) if is_transient_rpc_error(&e) => return Err(e),
+        Err(e) => {
+            debug!("Failed to fetch transaction {}: {}", signature, e);
+            return Ok(TransactionScan::Unavailable);
+        }
+    };
+    let scan = find_multisig_in_transaction(&transaction_details, authority, squads_program_id);
+    if let TransactionScan::Undecodable = scan {
+        debug!("Failed to decode transaction {}", signature);
+    }
+    Ok(scan)
+}
+
+/// Checks the accounts of a fetched transaction for the multisig `authority` is derived from.
+/// Accounts that a v0 transaction pulls in through an address lookup table aren't among its
+/// static keys, so they are read from the loaded addresses in the meta.
+fn find_multisig_in_transaction(
+    transaction_details: &EncodedConfirmedTransactionWithStatusMeta,
+    authority: &Pubkey,
+    squads_program_id: &Pubkey,
+) -> TransactionScan {
+    let Some(tx) = transaction_details.transaction.transaction.decode() else {
+        return TransactionScan::Undecodable;
+    };
+    let loaded_addresses = match transaction_details
+        .transaction
+        .meta
+        .as_ref()
+        .map(|meta| &meta.loaded_addresses)
+    {
+        Some(OptionSerializer::Some(loaded)) => loaded
+            .writable
+            .iter()
+            .chain(&loaded.readonly)
+            .map(|key| Pubkey::from_str(key))
+            .collect::<Result<Vec<_>, _>>()
+            .ok(),
+        _ => None,
+    };
+    let uses_lookup_tables = tx
+        .message
+        .address_table_lookups()
+        .is_some_and(|lookups| !lookups.is_empty());
+    let loaded_addresses = match loaded_addresses {
+        Some(loaded_addresses) => loaded_addresses,
+        // Without the meta some of the accounts can't be checked.
+        None if uses_lookup_tables => return TransactionScan::Undecodable,
+        None => vec![],
+    };
+    let accounts = tx
+        .message
+        .static_account_keys()
+        .iter()
+        .chain(&loaded_addresses)
+        .collect::<Vec<_>>();
+    for account in accounts.iter() {
+        let (derived_authority_key, _) =
+            derive_squads_authority_with_program_id(account, 1, squads_program_id);
+        if &derived_authority_key != authority {
+            continue;
+        }
+        return TransactionScan::Match(**account);
+    }
+    // Only when no account derives the authority as vault 1, to explain the miss.
+    for account in accounts {
+        for authority_index in (0..SCAN_VAULT_COUNT).filter(|i| *i != 1) {
+            let (derived_authority_key, _) = derive_squads_authority_with_program_id(
+                account,
+                authority_index,
+                squads_program_id,
+            );
+            if &derived_authority_key == authority {
+                return TransactionScan::OtherVault(*account, authority_index);
+            }
+        }
+    }
+    TransactionScan::NoMatch
+}
+
+/// Sends `ix` signed by `signers` and the fee payer, retrying up to
+/// `--max-retries` times. `multisig_key` is the multisig the instruction is about, as named in
+/// rejection logs. Returns `None` if preflight simulation rejects the transaction, and an error
+/// if the transaction still hasn't landed once the retries are exhausted. Pass `show_spinner` as
+/// false when other transactions are being confirmed at the same time.
+async fn execute(
+    ctx: &Context,
+    ix: Instruction,
+    multisig_key: &Pubkey,
+    signers: &[&Keypair],
+    fee_payer: &Keypair,
+    show_spinner: bool,
+) -> anyhow::Result<Option<Signature>> {
+    let sign = |blockhash| sign_transaction(fee_payer, &ix, signers, blockhash);
+    let tx = sign(ctx.latest_blockhash().await?);
+    send_with_retries(ctx, tx, multisig_key, Some(&sign), show_spinner).await
+}
+
+/// Signs a transaction of `ix` with `fee_payer` and `signers` at `blockhash`.
+fn sign_transaction(
+    fee_payer: &Keypair,
+    ix: &Instruction,
+    signers: &[&Keypair],
+    blockhash: Hash,
+) -> Transaction {
+    let mut all_signers = vec![fee_payer];
+    all_signers.extend(
+        signers
+            .iter()
+            .copied()
+            .filter(|signer| signer.pubkey() != fee_payer.pubkey()),
+    );
+    Transaction::new_signed_with_payer(
+        std::slice::from_ref(ix),
+        Some(&fee_payer.pubkey()),
+        &all_signers,
+        blockhash,
+    )
+}
+
+/// Sends the signed `tx` with the retry and confirmation logic of [`execute`]. When its blockhash
+/// expires, `resign` signs it again with a fresh one. Without it, as for transactions signed
+/// elsewhere, the expiry is returned as an error.
+async fn send_with_retries(
+    ctx: &Context,
+    mut tx: Transaction,
+    multisig_key: &Pubkey,
+    resign: Option<&dyn Fn(Hash) -> Transaction>,
+    show_spinner: bool,
+) -> anyhow::Result<Option<Signature>> {
+    let client = &ctx.client;
+    let commitment = client.commitment();
+    let config = RpcSendTransactionConfig {
+        skip_preflight: ctx.skip_preflight,
+        preflight_commitment: Some(commitment.commitment),
+        ..RpcSendTransactionConfig::default()
+    };
+    let mut retries = 0;
+    loop {
+        ctx.throttle.wait().await;
+        let send = async {
+            if show_spinner && !ctx.time {
+                client
+                    .send_and_confirm_transaction_with_spinner_and_config(&tx, commitment, config)
+                    .await
+            } else {
+                send_and_confirm_transaction(client, &tx, commitment, config, &ctx.timings).await
+            }
+        };
+        let sent = match ctx.confirm_timeout {
+            // Reported like a blockhash expiry, so the transaction is rechecked before a resend.
+            Some(confirm_timeout) => match tokio::time::timeout(confirm_timeout, send).await {
+                Ok(sent) => sent,
+                Err(_) => Err(RpcError::ForUser(format!(
+                    "unable to confirm transaction {} within {} seconds",
+                    tx.signatures[0],
+                    confirm_timeout.as_secs()
+                ))
+                .into()),
+            },
+            None => send.await,
+        };
+        let mut e = match sent {
+            Ok(signature) => {
+                ctx.throttle.succeeded();
+                return Ok(Some(signature));
+            }
+            Err(e) => e,
+        };
+        // A confirmation timeout doesn't mean the transaction was dropped, it often lands late
+        // under congestion. Resending it would only pay another fee.
+        if is_confirmation_timeout(&e) {
+            let signature = tx.signatures[0];
+            match recheck_timed_out(ctx, &tx).await {
+                TimeoutRecheck::Landed => {
+                    info!(
+                        "Transaction {} landed after confirmation timed out",
+                        signature
+                    );
+                    ctx.throttle.succeeded();
+                    return Ok(Some(signature));
+                }
+                TimeoutRecheck::Indexed(index_key) => {
+                    info!(
+                        "Index {} exists although transaction {} wasn't confirmed, not resending",
+                        index_key, signature
+                    );
+                    ctx.throttle.succeeded();
+                    return Ok(Some(signature));
+                }
+                TimeoutRecheck::Failed(err) => e = err.into(),
+                TimeoutRecheck::NotLanded => {}
+            }
+        }
+        if is_rate_limited(&e) {
+            ctx.throttle.rate_limited();
+        }
+        let action = classify_send_error(&e);
+        match action {
+            SendErrorAction::Landed => {
+                info!("Transaction {} was already processed", tx.signatures[0]);
+                return Ok(Some(tx.signatures[0]));
+            }
+            SendErrorAction::Rejected => {
+                let (reason, logs) = preflight_failure(&ctx.program_id, &e);
+                error!(
+                    "Simulation rejected the transaction for multisig {}: {}",
+                    multisig_key, reason
+                );
+                if !logs.is_empty() {
+                    error!("Program logs:\n  {}", logs.join("\n  "));
+                }
+                return Ok(None);
+            }
+            SendErrorAction::Abort => {
+                error!("Not retrying the transaction, the error won't go away on its own");
+                return Err(e.into());
+            }
+            SendErrorAction::RetryWithNewBlockhash if resign.is_none() => {
+                error!(
+                    "Blockhash {} expired before the transaction landed, sign it again",
+                    tx.message.recent_blockhash
+                );
+                return Err(e.into());
+            }
+            SendErrorAction::Retry | SendErrorAction::RetryWithNewBlockhash => {}
+        }
+        warn!("Attempt {}. Error sending transaction: {}", retries + 1, e);
+        if retries >= ctx.max_retries {
+            error!("Failed to send transaction after {} attempts", retries + 1);
+            return Err(e.into());
+        }
+        if !ctx.spend_retry() {
+            error!(
+                "Not resending the transaction: all {} resends of this run were used (--max-total-retries)",
+                ctx.max_total_retries.unwrap_or_default()
+            );
+            return Err(e.into());
+        }
+        retries += 1;
+        ctx.metrics.retries.fetch_add(1, Ordering::Relaxed);
+        if let (SendErrorAction::RetryWithNewBlockhash, Some(resign)) = (action, resign) {
+            tx = resign(ctx.refresh_blockhash(&tx.message.recent_blockhash).await?);
+        }
+    }
+}
+
+/// What a transaction whose confirmation timed out turned out to have done.
+#[derive(Debug, PartialEq)]
+enum TimeoutRecheck {
+    /// The transaction landed and succeeded.
+    Landed,
+    /// The transaction landed but failed.
+    Failed(TransactionError),
+    /// The transaction's status is unknown, but the index account it creates exists.
+    Indexed(Pubkey),
+    /// Nothing shows the transaction landed, so it is safe to resend.
+    NotLanded,
+}
+
+/// Whether a send failed because the transaction wasn't confirmed in time, as reported by both
+/// the spinner and [`send_and_confirm_transaction`].
+fn is_confirmation_timeout(error: &ClientError) -> bool {
+    matches!(
+        error.kind(),
+        ClientErrorKind::RpcError(RpcError::ForUser(message))
+            if message.starts_with("unable to confirm transaction")
+    )
+}
+
+/// The index account created by `tx`, or `None` if it doesn't create one. Only a create
+/// instruction's index account is evidence that the transaction, or an equivalent one, landed.
+fn created_index_key(tx: &Transaction) -> Option<Pubkey> {
+    let ix = tx.message.instructions.first()?;
+    match IndexInstruction::unpack(&ix.data).ok()? {
+        IndexInstruction::CreateIndex | IndexInstruction::CreateVaultIndex { .. } => {}
+        IndexInstruction::UpdateIndex
+        | IndexInstruction::CloseIndex
+        | IndexInstruction::MigrateIndex => return None,
+    }
+    let key_index = *ix.accounts.get(index_accounts::INDEX)?;
+    tx.message.account_keys.get(usize::from(key_index)).copied()
+}
+
+/// Decides what a timed out transaction did from its signature status and the account it
+/// creates, if any. The signature status wins, since the index may predate the transaction.
+fn timeout_recheck(
+    status: Option<Result<(), TransactionError>>,
+    created_index: Option<(Pubkey, Option<&Account>)>,
+    program_id: &Pubkey,
+) -> TimeoutRecheck {
+    match status {
+        Some(Ok(())) => TimeoutRecheck::Landed,
+        Some(Err(e)) => TimeoutRecheck::Failed(e),
+        None => match created_index {
+            Some((index_key, Some(index))) if index.owner == *program_id => {
+                TimeoutRecheck::Indexed(index_key)
+            }
+            _ => TimeoutRecheck::NotLanded,
+        },
+    }
+}
+
+/// Re-queries the signature status of a transaction whose confirmation timed out, and the index
+/// account it creates. A failed lookup counts as not landed, which only costs a resend.
+async fn recheck_timed_out(ctx: &Context, tx: &Transaction) -> TimeoutRecheck {
+    let signature = tx.signatures[0];
+    let status = match ctx
+        .client
+        .get_signature_status_with_commitment(&signature, ctx.client.commitment())
+        .await
+    {
+        Ok(status) => status,
+        Err(e) => {
+            debug!("Failed to recheck the status of {}: {}", signature, e);
+            None
+        }
+    };
+    let created_index = match created_index_key(tx) {
+        Some(index_key) if status.is_none() => match ctx.fetch_account(&index_key).await {
+            Ok(index) => Some((index_key, index)),
+            Err(e) => {
+                debug!("Failed to recheck index {}: {}", index_key, e);
+                None
+            }
+        },
+        _ => None,
+    };
+    let created_index = created_index
+        .as_ref()
+        .map(|(index_key, index)| (*index_key, index.as_ref()));
+    timeout_recheck(status, created_index, &ctx.program_id)
+}
+
+/// How often a transaction sent without the spinner is checked for confirmation.
+const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Sends `tx` and polls its status until it is confirmed or its blockhash expires, without
+/// drawing a spinner. An expired blockhash is reported like the spinner reports it, so `execute`
+/// re-signs the transaction in both cases.
+async fn send_and_confirm_transaction(
+    client: &RpcClient,
+    tx: &Transaction,
+    commitment: CommitmentConfig,
+    config: RpcSendTransactionConfig,
+    timings: &Timings,
+) -> Result<Signature, ClientError> {
+    let signature = {
+        let _timer = timings.start(Phase::Submission);
+        client.send_transaction_with_config(tx, config).await?
+    };
+    let _timer = timings.start(Phase::Confirmation);
+    loop {
+        match client
+            .get_signature_status_with_commitment(&signature, commitment)
+            .await?
+        {
+            Some(Ok(())) => return Ok(signature),
+            Some(Err(e)) => return Err(e.into()),
+            None => {}
+        }
+        if !client
+            .is_blockhash_valid(&tx.message.recent_blockhash, commitment)
+            .await?
+        {
+            return Err(RpcError::ForUser(format!(
+                "unable to confirm transaction {} before its blockhash expired",
+                signature
+            ))
+            .into());
+        }
+        tokio::time::sleep(CONFIRM_POLL_INTERVAL).await;
+    }
+}
+
+/// Extracts why preflight simulation rejected a transaction and the program logs it produced.
+fn preflight_failure(program_id: &Pubkey, error: &ClientError) -> (String, Vec<String>) {
+    let ClientErrorKind::RpcError(RpcError::RpcResponseError {
+        message,
+        data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+        ..
+    }) = error.kind()
+    else {
+        return (error.to_string(), vec![]);
+    };
+    let logs = result.logs.clone().unwrap_or_default();
+    let reason = match &result.err {
+        Some(err) => describe_transaction_error(program_id, err, &logs),
+        None => message.clone(),
+    };
+    (reason, logs)
+}
+
+/// Describes a transaction error, naming the index program's custom errors. Custom codes are only
+/// mapped to [`IndexError`] when the logs don't show another program, such as the system program
+/// during account creation, failing first.
+fn describe_transaction_error(
+    program_id: &Pubkey,
+    error: &TransactionError,
+    logs: &[String],
+) -> String {
+    let TransactionError::InstructionError(_, InstructionError::Custom(code)) = error else {
+        return error.to_string();
+    };
+    let failed_program = logs.iter().find_map(|log| {
+        let (program, _) = log.strip_prefix("Program ")?.split_once(" failed: ")?;
+        Pubkey::from_str(program).ok()
+    });
+    if failed_program.is_some_and(|failed_program| failed_program != *program_id) {
+        return error.to_string();
+    }
+    match IndexError::try_from(*code) {
+        Ok(IndexError::InvalidThreshold) => {
+            "the multisig threshold is zero or larger than its member count".to_string()
+        }
+        Ok(IndexError::UnsupportedVersion) => {
+            "the index account has a layout version this CLI doesn't support".to_string()
+        }
+        Ok(IndexError::ExecutableIndex) => "the index account is executable".to_string(),
+        Ok(IndexError::IllegalIndexOwner) => {
+            "the index address holds an account owned by another program".to_string()
+        }
+        Err(_) => error.to_string(),
+    }
+}
+
+/// How `execute` reacts to a failed send.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SendErrorAction {
+    /// Resend the same transaction.
+    Retry,
+    /// The blockhash expired, so re-sign with a fresh one before resending.
+    RetryWithNewBlockhash,
+    /// An earlier attempt already landed.
+    Landed,
+    /// Preflight simulation ran the index program and it rejected the accounts.
+    Rejected,
+    /// The error is deterministic, so retrying would only waste time.
+    Abort,
+}
+
+/// Classifies a failed send. Only network failures, rate limits and blockhash expiry are worth
+/// retrying. Errors such as insufficient funds come back identical on every attempt.
+fn classify_send_error(error: &ClientError) -> SendErrorAction {
+    match error.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+            ..
+        }) => match &result.err {
+            Some(TransactionError::InstructionError(..)) | None => SendErrorAction::Rejected,
+            Some(e) => classify_transaction_error(e),
+        },
+        // Without preflight a program error only surfaces once the transaction has landed.
+        ClientErrorKind::TransactionError(e) => classify_transaction_error(e),
+        // The transaction wasn't confirmed before its blockhash expired.
+        ClientErrorKind::RpcError(RpcError::ForUser(_)) => SendErrorAction::RetryWithNewBlockhash,
+        ClientErrorKind::RpcError(_) | ClientErrorKind::SerdeJson(_) => SendErrorAction::Retry,
+        ClientErrorKind::Reqwest(_) | ClientErrorKind::Io(_) if is_transient_rpc_error(error) => {
+            SendErrorAction::Retry
+        }
+        _ => SendErrorAction::Abort,
+    }
+}
+
+fn classify_transaction_error(error: &TransactionError) -> SendErrorAction {
+    match error {
+        TransactionError::BlockhashNotFound => SendErrorAction::RetryWithNewBlockhash,
+        TransactionError::AlreadyProcessed => SendErrorAction::Landed,
+        // Contention and congestion clear up on their own.
+        TransactionError::AccountInUse
+        | TransactionError::ClusterMaintenance
+        | TransactionError::WouldExceedMaxBlockCostLimit
+        | TransactionError::WouldExceedMaxAccountCostLimit
+        | TransactionError::WouldExceedAccountDataBlockLimit => SendErrorAction::Retry,
+        _ => SendErrorAction::Abort,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anchor_lang::AnchorSerialize;
+    use solana_sdk::signer::SignerError;
+    use squads_v3_index::derive_squads_authority;
+
+    fn simulation_failure(err: TransactionError, logs: &[&str]) -> ClientError {
+        let result =
+            serde_json::from_value(serde_json::json!({ "err": err, "logs": logs })).unwrap();
+        ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: -32002,
+            message: "Transaction simulation failed".to_string(),
+            data: RpcResponseErrorData::SendTransactionPreflightFailure(result),
+        })
+        .into()
+    }
+
+    #[test]
+    fn test_classify_send_error_retries_transient_errors() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(
+            classify_send_error(&ClientErrorKind::Io(io).into()),
+            SendErrorAction::Retry
+        );
+        let node_behind = ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: -32005,
+            message: "Node is behind".to_string(),
+            data: RpcResponseErrorData::Empty,
+        });
+        assert_eq!(
+            classify_send_error(&node_behind.into()),
+            SendErrorAction::Retry
+        );
+        assert_eq!(
+            classify_send_error(&TransactionError::AccountInUse.into()),
+            SendErrorAction::Retry
+        );
+    }
+
+    #[test]
+    fn test_min_context_slot_not_reached_is_transient() {
+        let lagging = ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: JSON_RPC_SERVER_ERROR_MIN_CONTEXT_SLOT_NOT_REACHED,
+            message: "Minimum context slot has not been reached".to_string(),
+            data: RpcResponseErrorData::Empty,
+        });
+        assert!(is_transient_rpc_error(&lagging.into()));
+        let invalid = ClientErrorKind::RpcError(RpcError::RpcResponseError {
+            code: -32602,
+            message: "Invalid params".to_string(),
+            data: RpcResponseErrorData::Empty,
+        });
+        assert!(!is_transient_rpc_error(&invalid.into()));
+    }
+
+    #[test]
+    fn test_classify_send_error_refreshes_expired_blockhash() {
+        assert_eq!(
+            classify_send_error(&simulation_failure(
+                TransactionError::BlockhashNotFound,
+                &[]
+            )),
+            SendErrorAction::RetryWithNewBlockhash
+        );
+        let unconfirmed = ClientErrorKind::RpcError(RpcError::ForUser(
+            "unable to confirm transaction".to_string(),
+        ));
+        assert_eq!(
+            classify_send_error(&unconfirmed.into()),
+            SendErrorAction::RetryWithNewBlockhash
+        );
+    }
+
+    #[test]
+    fn test_classify_send_error_aborts_deterministic_errors() {
+        assert_eq!(
+            classify_send_error(&simulation_failure(
+                TransactionError::InsufficientFundsForFee,
+                &[]
+            )),
+            SendErrorAction::Abort
+        );
+        assert_eq!(
+            classify_send_error(&simulation_failure(TransactionError::AccountNotFound, &[])),
+            SendErrorAction::Abort
+        );
+        let program_error = TransactionError::InstructionError(0, InstructionError::Custom(0));
+        assert_eq!(
+            classify_send_error(&program_error.into()),
+            SendErrorAction::Abort
+        );
+        let signing = ClientErrorKind::SigningError(SignerError::Custom("no key".to_string()));
+        assert_eq!(classify_send_error(&signing.into()), SendErrorAction::Abort);
+        assert_eq!(
+            classify_send_error(&ClientErrorKind::Custom("bad request".to_string()).into()),
+            SendErrorAction::Abort
+        );
+    }
+
+    #[test]
+    fn test_classify_send_error_rejected_and_landed() {
+        let program_error =
+            TransactionError::InstructionError(0, InstructionError::InvalidArgument);
+        assert_eq!(
+            classify_send_error(&simulation_failure(program_error, &[])),
+            SendErrorAction::Rejected
+        );
+        assert_eq!(
+            classify_send_error(&TransactionError::AlreadyProcessed.into()),
+            SendErrorAction::Landed
+        );
+    }
+
+    #[test]
+    fn test_is_confirmation_timeout() {
+        // The spinner's message, the one of `send_and_confirm_transaction`, and `--confirm-timeout`.
+        for message in [
+            "unable to confirm transaction. This can happen in situations such as transaction \
+             expiration and insufficient fee-payer funds",
+            "unable to confirm transaction 1111 before its blockhash expired",
+            "unable to confirm transaction 1111 within 20 seconds",
+        ] {
+            let timeout = ClientErrorKind::RpcError(RpcError::ForUser(message.to_string()));
+            assert!(is_confirmation_timeout(&timeout.into()));
+        }
+        let other = ClientErrorKind::RpcError(RpcError::ForUser("node is behind".to_string()));
+        assert!(!is_confirmation_timeout(&other.into()));
+        assert!(!is_confirmation_timeout(
+            &TransactionError::BlockhashNotFound.into()
+        ));
+    }
+
+    #[test]
+    fn test_timeout_recheck_landed() {
+        let program_id = squads_v3_index::id();
+        let index_key = Pubkey::new_unique();
+        let index = Account {
+            owner: program_id,
+            ..Account::default()
+        };
+        // The transaction landed although confirmation timed out.
+        assert_eq!(
+            timeout_recheck(Some(Ok(())), None, &program_id),
+            TimeoutRecheck::Landed
+        );
+        assert_eq!(
+            timeout_recheck(Some(Ok(())), Some((index_key, None)), &program_id),
+            TimeoutRecheck::Landed
+        );
+        // The status isn't known yet, but the index it creates exists.
+        assert_eq!(
+            timeout_recheck(None, Some((index_key, Some(&index))), &program_id),
+            TimeoutRecheck::Indexed(index_key)
+        );
+        assert_eq!(
+            timeout_recheck(
+                Some(Err(TransactionError::InsufficientFundsForRent {
+                    account_index: 1
+                })),
+                Some((index_key, Some(&index))),
+                &program_id
+            ),
+            TimeoutRecheck::Failed(TransactionError::InsufficientFundsForRent { account_index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_timeout_recheck_not_landed() {
+        let program_id = squads_v3_index::id();
+        let index_key = Pubkey::new_unique();
+        let prefunded = Account {
+            lamports: 1,
+            owner: system_program::id(),
+            ..Account::default()
+        };
+        assert_eq!(
+            timeout_recheck(None, None, &program_id),
+            TimeoutRecheck::NotLanded
+        );
+        assert_eq!(
+            timeout_recheck(None, Some((index_key, None)), &program_id),
+            TimeoutRecheck::NotLanded
+        );
+        assert_eq!(
+            timeout_recheck(None, Some((index_key, Some(&prefunded))), &program_id),
+            TimeoutRecheck::NotLanded
+        );
+    }
+
+    #[test]
+    fn test_created_index_key() {
+        let multisig = Pubkey::new_unique();
+        let authority = derive_squads_authority(&multisig, 1).0;
+        let payer = Pubkey::new_unique();
+        let tx_of = |instruction| {
+            let ix = index_instruction(
+                instruction,
+                squads_v3_index::id(),
+                &authority,
+                &multisig,
+                &payer,
+                None,
+            );
+            Transaction::new_with_payer(&[ix], Some(&payer))
+        };
+        let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+        assert_eq!(
+            created_index_key(&tx_of(IndexInstruction::CreateIndex)),
+            Some(index_key)
+        );
+        assert_eq!(
+            created_index_key(&tx_of(IndexInstruction::CreateVaultIndex {
+                authority_index: 1
+            })),
+            Some(index_key)
+        );
+        // An update's index existed before the transaction, so it proves nothing.
+        assert_eq!(
+            created_index_key(&tx_of(IndexInstruction::UpdateIndex)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_preflight_failure_names_index_errors() {
+        let program_id = squads_v3_index::id();
+        let invalid_threshold = TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(IndexError::InvalidThreshold as u32),
+        );
+        let logs = [
+            "Program idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr invoke [1]",
+            "Program idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr failed: custom program error: 0x0",
+        ];
+        let (reason, reported_logs) = preflight_failure(
+            &program_id,
+            &simulation_failure(invalid_threshold.clone(), &logs),
+        );
+        assert_eq!(
+            reason,
+            "the multisig threshold is zero or larger than its member count"
+        );
+        assert_eq!(reported_logs, logs);
+
+        // The system program's AccountAlreadyInUse shares custom code 0.
+        let logs = [
+            "Program idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr invoke [1]",
+            "Program 11111111111111111111111111111111 invoke [2]",
+            "Program 11111111111111111111111111111111 failed: custom program error: 0x0",
+            "Program idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr failed: custom program error: 0x0",
+        ];
+        let (reason, _) = preflight_failure(
+            &program_id,
+            &simulation_failure(invalid_threshold.clone(), &logs),
+        );
+        assert_eq!(reason, invalid_threshold.to_string());
+
+        let (reason, _) = preflight_failure(
+            &program_id,
+            &simulation_failure(
+                TransactionError::InsufficientFundsForRent { account_index: 3 },
+                &[],
+            ),
+        );
+        assert_eq!(
+            reason,
+            TransactionError::InsufficientFundsForRent { account_index: 3 }.to_string()
+        );
+    }
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = Metrics::default();
+        metrics
+            .transactions_scanned
+            .fetch_add(12, Ordering::Relaxed);
+        metrics.matches_found.fetch_add(1, Ordering::Relaxed);
+        let rendered = metrics.render(15, Duration::from_millis(1250), Duration::from_millis(2500));
+        let samples = rendered
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [
+                "squads_index_transactions_scanned 12",
+                "squads_index_matches_found 1",
+                "squads_index_scan_seconds 1.250",
+                "squads_index_retries 0",
+                "squads_index_rpc_calls 15",
+                "squads_index_elapsed_seconds 2.500",
+            ]
+        );
+        assert!(rendered.contains("# TYPE squads_index_elapsed_seconds gauge\n"));
+    }
+
+    #[test]
+    fn test_throttle_backoff() {
+        let throttle = Throttle::new(Duration::from_millis(100));
+        throttle.rate_limited();
+        assert_eq!(throttle.delay(), THROTTLE_MIN_BACKOFF);
+        throttle.rate_limited();
+        assert_eq!(throttle.delay(), THROTTLE_MIN_BACKOFF * 2);
+        for _ in 0..10 {
+            throttle.rate_limited();
+        }
+        assert_eq!(throttle.delay(), THROTTLE_MAX_BACKOFF);
+        // Recovering never goes below the configured delay.
+        for _ in 0..10 {
+            throttle.succeeded();
+        }
+        assert_eq!(throttle.delay(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_timings_render() {
+        let timings = Timings::default();
+        timings.nanos[Phase::Scan as usize].fetch_add(2_000_000_000, Ordering::Relaxed);
+        timings.scan_rpc_calls.fetch_add(21, Ordering::Relaxed);
+        drop(timings.start(Phase::Confirmation));
+        let rendered = timings.render(20, 30, Duration::from_millis(2500));
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[2],
+            "  History scan             2.000s (20 transactions, 10.0 tx/s, 21 RPC calls)"
+        );
+        assert_eq!(lines[5], "  Total                    2.500s (30 RPC calls)");
+    }
+
+    #[test]
+    fn test_decode_signed_transaction() {
+        let signed = |tx: &Transaction| SignedIndex {
+            address: Pubkey::new_unique().to_string(),
+            authority: Pubkey::new_unique().to_string(),
+            authority_index: 1,
+            multisig: Pubkey::new_unique().to_string(),
+            index: Pubkey::new_unique().to_string(),
+            is_program: false,
+            transaction: BASE64_STANDARD.encode(bincode::serialize(tx).unwrap()),
+        };
+        let payer = Keypair::new();
+        let message = Message::new(&[], Some(&payer.pubkey()));
+
+        let tx = Transaction::new(&[&payer], message.clone(), Hash::default());
+        assert_eq!(decode_signed_transaction(&signed(&tx)).unwrap(), tx);
+        // Without signatures or accounts, `is_signed` alone would let it through.
+        for tx in [Transaction::default(), Transaction::new_unsigned(message)] {
+            assert!(matches!(
+                decode_signed_transaction(&signed(&tx)),
+                Err(CliError::Invalid(_))
+            ));
+        }
+        let mut not_base64 = signed(&tx);
+        not_base64.transaction = "not base64".to_string();
+        assert!(matches!(
+            decode_signed_transaction(&not_base64),
+            Err(CliError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        assert!(hex_dump(&[]).is_empty());
+        let data = (0..=20).collect::<Vec<u8>>();
+        assert_eq!(
+            hex_dump(&data),
+            [
+                "0000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f",
+                "0010  10 11 12 13 14",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_creation() {
+        let tx = |signature: &str, slot: Slot, failed: bool| {
+            RpcConfirmedTransactionStatusWithSignature {
+                signature: signature.to_string(),
+                slot,
+                err: failed.then_some(TransactionError::AccountNotFound),
+                memo: None,
+                block_time: None,
+                confirmation_status: None,
+            }
+        };
+        // Newest first, as the RPC returns it: closed at slot 30 and created again at slot 40.
+        let history = [
+            tx("update", 50, false),
+            tx("recreate", 40, false),
+            tx("close", 30, false),
+            tx("create", 10, false),
+            tx("failed", 5, true),
+        ];
+        let signature =
+            |created_slot| find_creation(&history, created_slot).map(|tx| tx.signature.as_str());
+        assert_eq!(signature(Some(40)), Some("recreate"));
+        assert_eq!(signature(None), Some("create"));
+        assert_eq!(signature(Some(5)), None);
+        assert_eq!(find_creation(&history[..1], Some(40)), None);
+        assert_eq!(find_creation(&[], None), None);
+    }
+
+    #[test]
+    fn test_format_unix_time() {
+        assert_eq!(format_unix_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_unix_time(1_700_000_000), "2023-11-14 22:13:20 UTC");
+        assert_eq!(format_unix_time(951_825_600), "2000-02-29 12:00:00 UTC");
+        assert_eq!(format_unix_time(-1), "1969-12-31 23:59:59 UTC");
+    }
+
+    #[test]
+    fn test_render_rpc_usage() {
+        assert_eq!(render_rpc_usage(3, 0), "RPC requests: 3");
+        assert_eq!(
+            render_rpc_usage(250, 212),
+            "RPC requests: 250 (212 scanning history)"
+        );
+    }
+
+    #[test]
+    fn test_resolve_network_url() {
+        let config = Config {
+            json_rpc_url: "https://rpc.example.com".to_string(),
+            ..Config::default()
+        };
+        // The Solana CLI config is the default, not the public mainnet endpoint.
+        assert_eq!(
+            resolve_network_url(None, None, &config),
+            "https://rpc.example.com"
+        );
+        assert_eq!(
+            resolve_network_url(None, Some("dev".to_string()), &config),
+            "https://api.devnet.solana.com"
+        );
+        assert_eq!(
+            resolve_network_url(Some("main".to_string()), Some("dev".to_string()), &config),
+            MAINNET_URL
+        );
+        // Aliases are expanded wherever the URL comes from.
+        let config = Config {
+            json_rpc_url: "l".to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            resolve_network_url(None, None, &config),
+            "http://localhost:8899"
+        );
+        let config = Config {
+            json_rpc_url: String::new(),
+            ..Config::default()
+        };
+        assert_eq!(resolve_network_url(None, None, &config), MAINNET_URL);
+        assert_eq!(
+            resolve_network_url(None, None, &Config::default()),
+            MAINNET_URL
+        );
+    }
+
+    #[test]
+    fn test_encoded_instruction() {
+        let multisig = Pubkey::new_unique();
+        let authority = derive_squads_authority(&multisig, 1).0;
+        let ix = index_instruction(
+            IndexInstruction::CreateIndex,
+            squads_v3_index::id(),
+            &authority,
+            &multisig,
+            &authority,
+            None,
+        );
+        let encoded = serde_json::to_value(EncodedInstruction::from(&ix)).unwrap();
+        assert_eq!(encoded["data_base64"], "AA==");
+        assert_eq!(encoded["data"], "1");
+        assert_eq!(encoded["accounts"][1]["role"], "Authority (Squads Vault)");
+        assert_eq!(encoded["accounts"][3]["pubkey"], authority.to_string());
+        assert_eq!(encoded["accounts"][3]["is_signer"], true);
+        assert_eq!(encoded["accounts"][4]["role"], "Index PDA");
+
+        // Accounts of an unknown instruction have no role.
+        let ix = Instruction {
+            data: vec![9],
+            ..ix
+        };
+        let encoded = serde_json::to_value(EncodedInstruction::from(&ix)).unwrap();
+        assert!(encoded["accounts"][0].get("role").is_none());
+    }
+
+    #[test]
+    fn test_check_result_status() {
+        let result = |outcome| CheckResult {
+            address: Pubkey::new_unique(),
+            is_program: true,
+            authority: Some(Pubkey::new_unique()),
+            index: None,
+            outcome,
+        };
+        assert_eq!(result(CheckOutcome::Immutable).status(), Status::Success);
+        assert_eq!(result(CheckOutcome::NotPda).status(), Status::Invalid);
+        assert_eq!(
+            result(CheckOutcome::NotSquadsAuthority).status(),
+            Status::Invalid
+        );
+        // A vault other than 1 is a Squads authority, just not one `index` links for a program.
+        let other_vault = CheckOutcome::OtherVault {
+            multisig: Pubkey::new_unique(),
+            authority_index: 2,
+        };
+        assert_eq!(result(other_vault).status(), Status::NotIndexed);
+        assert_eq!(
+            result(CheckOutcome::Uninitialized { lamports: 1 }).status(),
+            Status::NotIndexed
+        );
+        assert_eq!(
+            result(CheckOutcome::NotIndexed).status(),
+            Status::NotIndexed
+        );
+        let indexed = CheckOutcome::Indexed {
+            index_account: None,
+            legacy: true,
+            multisig: None,
+            multisig_account: None,
+        };
+        assert_eq!(result(indexed).status(), Status::Success);
+    }
+
+    #[test]
+    fn test_keypair_from_env() {
+        let keypair = Keypair::new();
+        let json = serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap();
+        std::env::set_var("SQUADS_INDEX_TEST_KEYPAIR", &json);
+        let loaded = get_payer_keypair_from_path("env:SQUADS_INDEX_TEST_KEYPAIR").unwrap();
+        assert_eq!(loaded.pubkey(), keypair.pubkey());
+
+        // Errors never echo the key material.
+        let secret = "[1, 2, \"not a byte\"]";
+        std::env::set_var("SQUADS_INDEX_TEST_BAD_KEYPAIR", secret);
+        let error = get_payer_keypair_from_path("env:SQUADS_INDEX_TEST_BAD_KEYPAIR")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("$SQUADS_INDEX_TEST_BAD_KEYPAIR"));
+        assert!(!error.contains("not a byte"));
+        let error = parse_keypair_json(&json[..json.len() - 1], "stdin")
+            .unwrap_err()
+            .to_string();
+        assert!(!error.contains(&json[1..20]));
+
+        assert!(get_payer_keypair_from_path("env:SQUADS_INDEX_TEST_UNSET_KEYPAIR").is_err());
+    }
+
+    fn lookup_table_transaction() -> EncodedConfirmedTransactionWithStatusMeta {
+        serde_json::from_str(include_str!(
+            "../tests/fixtures/v0_lookup_table_transaction.json"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_find_multisig_in_lookup_table_accounts() {
+        // The multisig is only referenced through the transaction's address lookup table.
+        let multisig = Pubkey::from_str("DrRXnUL8rPUVSk8XgwMaNbGzrc4XvA3NfwfGxKdo1LDr").unwrap();
+        let (authority, _) = derive_squads_authority(&multisig, 1);
+        let transaction_details = lookup_table_transaction();
+        assert_eq!(
+            find_multisig_in_transaction(&transaction_details, &authority, &squads_mpl::id()),
+            TransactionScan::Match(multisig)
+        );
+        let (vault_3, _) = derive_squads_authority(&multisig, 3);
+        assert_eq!(
+            find_multisig_in_transaction(&transaction_details, &vault_3, &squads_mpl::id()),
+            TransactionScan::OtherVault(multisig, 3)
+        );
+        assert_eq!(
+            find_multisig_in_transaction(
+                &transaction_details,
+                &Pubkey::new_unique(),
+                &squads_mpl::id()
+            ),
+            TransactionScan::NoMatch
+        );
+    }
+
+    #[test]
+    fn test_find_multisig_without_loaded_addresses() {
+        let mut transaction_details = lookup_table_transaction();
+        transaction_details.transaction.meta = None;
+        assert_eq!(
+            find_multisig_in_transaction(
+                &transaction_details,
+                &Pubkey::new_unique(),
+                &squads_mpl::id()
+            ),
+            TransactionScan::Undecodable
+        );
+    }
+
+    #[test]
+    fn test_cli_error_from_anyhow() {
+        // RPC failures wrapped in `anyhow` still exit with the RPC error status.
+        let client_error = ClientError::from(ClientErrorKind::Custom("unreachable".to_string()));
+        let error = CliError::from(anyhow::Error::from(client_error));
+        assert!(matches!(error, CliError::Rpc(_)));
+        assert_eq!(error.exit_code(), Status::RpcError as u8);
+        assert_eq!(error.kind(), "rpc_error");
+
+        let error = CliError::from(anyhow!("something else"));
+        assert!(matches!(error, CliError::Other(_)));
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(error.to_string(), "something else");
+
+        assert_eq!(
+            CliError::NotIndexed(Pubkey::new_unique()).exit_code(),
+            Status::NotIndexed as u8
+        );
+        assert_eq!(CliError::Aborted.exit_code(), Status::Aborted as u8);
+    }
+
+    /// Serializes a 1 of `members` Squads V3 `Ms` account the way Anchor stores it.
+    fn multisig_data(members: usize) -> Vec<u8> {
+        let ms = Ms {
+            threshold: 1,
+            authority_index: 1,
+            transaction_index: 0,
+            ms_change_index: 0,
+            bump: 255,
+            create_key: Pubkey::new_unique(),
+            allow_external_execute: false,
+            keys: (0..members).map(|_| Pubkey::new_unique()).collect(),
+        };
+        [Ms::DISCRIMINATOR.to_vec(), ms.try_to_vec().unwrap()].concat()
+    }
+
+    #[test]
+    fn test_check_report() {
+        let multisig = Pubkey::new_unique();
+        let authority = derive_squads_authority(&multisig, 1).0;
+        let ms = decode_multisig(&multisig_data(2)).unwrap();
+        let members = ms.keys.iter().map(Pubkey::to_string).collect::<Vec<_>>();
+        let result = CheckResult {
+            address: authority,
+            is_program: false,
+            authority: Some(authority),
+            index: Some(Pubkey::new_unique()),
+            outcome: CheckOutcome::Indexed {
+                index_account: Some(IndexAccount::new(multisig, 1, 255, 10)),
+                legacy: false,
+                multisig: Some(multisig),
+                multisig_account: Some(ms),
+            },
+        };
+        let report = result.report();
+        assert_eq!(report.state, "indexed");
+        assert_eq!(report.multisig, Some(multisig.to_string()));
+        assert_eq!(report.authority_index, Some(1));
+        assert_eq!(report.threshold, Some(1));
+        assert_eq!(report.members, members);
+        assert_eq!(report.created_slot, Some(10));
+        assert_eq!(report.updated_slot, None);
+
+        let result = CheckResult {
+            outcome: CheckOutcome::NotIndexed,
+            ..result
+        };
+        let json = serde_json::to_value(result.report()).unwrap();
+        assert_eq!(json["state"], "not_indexed");
+        assert_eq!(json["members"], serde_json::json!([]));
+        assert!(json.get("lamports").is_none());
+    }
+
+    #[test]
+    fn test_decode_multisig() {
+        let data = multisig_data(3);
+        assert_eq!(decode_multisig(&data).unwrap().keys.len(), 3);
+        // Trailing space after the members, as allocated for future members, is ignored.
+        let mut padded = data.clone();
+        padded.extend_from_slice(&[0; 64]);
+        assert_eq!(decode_multisig(&padded).unwrap().keys.len(), 3);
+
+        assert_eq!(
+            decode_multisig(&[0; 100]).err(),
+            Some(MultisigDataError::NotMultisig)
+        );
+        for len in 0..8 {
+            assert_eq!(
+                decode_multisig(&data[..len]).err(),
+                Some(MultisigDataError::NotMultisig)
+            );
+        }
+        for len in 8..data.len() {
+            assert_eq!(
+                decode_multisig(&data[..len]).err(),
+                Some(MultisigDataError::Malformed),
+                "truncated to {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_multisig_oversized_member_count() {
+        let mut data = multisig_data(1);
+        data[MS_KEYS_LEN_OFFSET..MS_KEYS_LEN_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            decode_multisig(&data).err(),
+            Some(MultisigDataError::Malformed)
+        );
+        assert!(parse_multisig(&data).is_none());
+    }
+
+    #[test]
+    fn test_keypair_pool_paths() {
+        assert_eq!(
+            keypair_pool_paths("a.json, b.json,,").unwrap(),
+            vec!["a.json".to_string(), "b.json".to_string()]
+        );
+        assert!(keypair_pool_paths(" , ").is_err());
+
+        let dir = std::env::temp_dir().join(format!("keypair-pool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.json", "a.json", "notes.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let paths = keypair_pool_paths(dir.to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                dir.join("a.json").display().to_string(),
+                dir.join("b.json").display().to_string()
+            ]
+        );
+    }
+
+    fn proof(multisig: &Pubkey, authority_index: u32) -> IndexProof {
+        let program = Pubkey::new_unique();
+        let (authority, authority_bump) = derive_squads_authority(multisig, authority_index);
+        let (index_key, index_bump) = find_index_address(&authority, &squads_v3_index::id());
+        IndexProof {
+            version: PROOF_VERSION,
+            slot: 100,
+            program: program.to_string(),
+            program_data: get_program_data_address(&program).to_string(),
+            upgrade_authority: authority.to_string(),
+            multisig: multisig.to_string(),
+            authority_index,
+            threshold: 2,
+            members: (0..3).map(|_| Pubkey::new_unique().to_string()).collect(),
+            authority_derivation: PdaDerivation::new(
+                authority,
+                &squads_mpl::id(),
+                authority_seeds(multisig, authority_index),
+                authority_bump,
+            ),
+            index_derivation: PdaDerivation::new(
+                index_key,
+                &squads_v3_index::id(),
+                index_seeds(&authority),
+                index_bump,
+            ),
+            index: IndexEvidence {
+                owner: squads_v3_index::id().to_string(),
+                multisig: multisig.to_string(),
+                authority_index,
+                created_slot: 90,
+            },
+        }
+    }
+
+    #[test]
+    fn test_verify_proof_offline() {
+        let failed = |proof: &IndexProof| {
+            verify_proof_offline(proof, &squads_v3_index::id(), &squads_mpl::id())
+                .into_iter()
+                .filter(|check| !check.ok)
+                .map(|check| check.name)
+                .collect::<Vec<_>>()
+        };
+        let multisig = Pubkey::new_unique();
+        let valid = proof(&multisig, 1);
+        assert!(failed(&valid).is_empty());
+        let json = serde_json::to_string(&valid).unwrap();
+        assert_eq!(serde_json::from_str::<IndexProof>(&json).unwrap(), valid);
+
+        // Claiming another vault of the multisig breaks the derivation.
+        let mut other_vault = proof(&multisig, 1);
+        other_vault.authority_index = 2;
+        assert_eq!(
+            failed(&other_vault),
+            vec!["Authority derivation", "Index contents"]
+        );
+        // So does a derivation under another program.
+        let mut other_program = proof(&multisig, 1);
+        other_program.authority_derivation.program_id = Pubkey::new_unique().to_string();
+        assert_eq!(failed(&other_program), vec!["Authority derivation"]);
+        let mut wrong_bump = proof(&multisig, 1);
+        wrong_bump.index_derivation.bump = wrong_bump.index_derivation.bump.wrapping_add(1);
+        assert_eq!(failed(&wrong_bump), vec!["Index derivation"]);
+        let mut other_multisig = proof(&multisig, 1);
+        other_multisig.index.multisig = Pubkey::new_unique().to_string();
+        assert_eq!(failed(&other_multisig), vec!["Index contents"]);
+        let mut threshold = proof(&multisig, 1);
+        threshold.threshold = 4;
+        assert_eq!(failed(&threshold), vec!["Threshold"]);
+    }
+
+    #[test]
+    fn test_orphan_reason() {
+        let program_id = squads_v3_index::id();
+        let squads_program_id = squads_mpl::id();
+        let multisig_key = Pubkey::new_unique();
+        let authority = derive_squads_authority(&multisig_key, 1).0;
+        let index_key = find_index_address(&authority, &program_id).0;
+        let reason = |index_key: &Pubkey, multisig: Option<&Account>| {
+            orphan_reason(
+                &program_id,
+                &squads_program_id,
+                index_key,
+                &authority,
+                multisig,
+            )
+        };
+        let multisig = Account {
+            data: multisig_data(2),
+            owner: squads_program_id,
+            ..Account::default()
+        };
+        assert_eq!(reason(&index_key, Some(&multisig)), None);
+        assert_eq!(
+            reason(&index_key, None),
+            Some(OrphanReason::MultisigMissing)
+        );
+        let not_a_multisig = Account {
+            owner: system_program::id(),
+            ..multisig.clone()
+        };
+        assert_eq!(
+            reason(&index_key, Some(&not_a_multisig)),
+            Some(OrphanReason::NotAMultisig)
+        );
+        assert_eq!(
+            reason(&Pubkey::new_unique(), Some(&multisig)),
+            Some(OrphanReason::DerivationMismatch)
+        );
+        assert!(OrphanReason::NoProgram.closeable());
+        assert!(!OrphanReason::MultisigMissing.closeable());
+    }
+
+    #[test]
+    fn test_parse_batch_lines() {
+        let address = Pubkey::new_unique();
+        let items = parse_batch_lines(&format!("# programs\n\n  {}\nnot-an-address\n", address));
+        assert_eq!(items.len(), 2);
+        assert_eq!(
+            items[0],
+            ("line 3".to_string(), Ok(IndexTarget::from(address)))
+        );
+        assert_eq!(items[1].0, "line 4");
+        assert!(items[1].1.is_err());
+    }
+
+    #[test]
+    fn test_parse_batch_json() {
+        let multisig = Pubkey::new_unique();
+        let json = format!(
+            r#"[
+                {{ "address": "{multisig}" }},
+                {{ "address": "{multisig}", "authority_index": 2 }},
+                {{ "authority_index": 2 }},
+                {{ "address": "{multisig}", "authority_index": -1 }},
+                {{ "address": "{multisig}", "vault": 2 }},
+                {{ "address": "nope" }}
+            ]"#
+        );
+        let items = parse_batch_json(&json).unwrap();
+        let targets = items
+            .iter()
+            .map(|(_, target)| target.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(targets[0], Ok(IndexTarget::from(multisig)));
+        assert_eq!(
+            targets[1],
+            Ok(IndexTarget {
+                address: multisig,
+                authority_index: 2,
+                multisig: None,
+                expected_index: None,
+            })
+        );
+        assert!(targets[2]
+            .as_ref()
+            .unwrap_err()
+            .contains("missing field `address`"));
+        assert!(targets[3].is_err());
+        assert!(targets[4]
+            .as_ref()
+            .unwrap_err()
+            .contains("unknown field `vault`"));
+        assert!(targets[5]
+            .as_ref()
+            .unwrap_err()
+            .contains("is not a valid address"));
+        assert_eq!(items[5].0, "entry 6");
+        // Only a document that isn't an array fails as a whole.
+        assert!(parse_batch_json(&format!(r#"{{ "address": "{multisig}" }}"#)).is_err());
     }
-    println!("Successfully created index for {}", authority_key);
-    Ok(())
 }
@@ -1,21 +1,420 @@
+//! Links program upgrade authorities to the Squads V3 multisig that controls them.
+//!
+//! Each index account is a PDA derived from `[authority]` and owned by this program. Its data
+//! is laid out as follows:
+//!
+//! | Offset | Size | Field             | Description                                      |
+//! |--------|------|-------------------|--------------------------------------------------|
+//! | 0      | 8    | discriminator     | [`IndexAccount::DISCRIMINATOR`]                  |
+//! | 8      | 1    | `version`         | Layout version, currently `1`                    |
+//! | 9      | 32   | `multisig`        | The Squads V3 multisig the authority belongs to  |
+//! | 41     | 4    | `authority_index` | Squads authority index the authority derives from |
+//! | 45     | 1    | `bump`            | Canonical bump seed of the index PDA              |
+//! | 46     | 8    | `created_slot`    | Slot the index was created in                    |
+//! | 54     | 8    | `updated_slot`    | Last `UpdateIndex` slot, `0` if never updated    |
+//!
+//! The version byte comes right after the discriminator so that a future layout can change
+//! everything after it without a new discriminator. Empty index accounts were created before any
+//! data was stored.
+//!
+//! Use [`IndexAccount::try_from_account_data`] to read it. Storing the bump lets consumers
+//! re-derive the index address with [`create_index_address`] instead of paying for a bump search
+//! on every lookup.
+
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::AccountInfo,
     declare_id,
     entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
-    pubkey::Pubkey,
+    pubkey::{Pubkey, PubkeyError},
     system_instruction, system_program,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
 
 declare_id!("idxqM2xnXsym7KL9YQmC8GG6TvdV9XxvHeMWdiswpwr");
 
+/// The Squads V3 program the index program trusts.
+///
+/// By default this is the mainnet deployment, [`squads_mpl::MAINNET_ID`]. Building with the
+/// `custom-squads-program-id` feature replaces it with the id in the `SQUADS_PROGRAM_ID`
+/// environment variable at compile time, for a fork or a copy deployed to a local validator.
 pub mod squads_mpl {
-    use solana_program::declare_id;
-    declare_id!("SMPLecH534NA9acpos4G6x7uf3LWbCAwZQE9e8ZekMu");
+    use solana_program::pubkey::Pubkey;
+
+    /// Program id of Squads V3 on mainnet.
+    pub const MAINNET_ID: Pubkey =
+        solana_program::pubkey!("SMPLecH534NA9acpos4G6x7uf3LWbCAwZQE9e8ZekMu");
+
+    #[cfg(not(feature = "custom-squads-program-id"))]
+    pub const ID: Pubkey = MAINNET_ID;
+
+    #[cfg(feature = "custom-squads-program-id")]
+    pub const ID: Pubkey = Pubkey::new_from_array(crate::decode_pubkey(env!("SQUADS_PROGRAM_ID")));
+
+    pub fn id() -> Pubkey {
+        ID
+    }
+
+    /// Anchor discriminator of the Squads V3 `Ms` account.
+    pub const MS_DISCRIMINATOR: [u8; 8] = [70, 118, 9, 108, 254, 215, 31, 120];
+
+    // Byte offsets into the Squads V3 `Ms` account. The fields are Borsh encoded after the
+    // discriminator in this order:
+    //
+    //   threshold: u16               [8..10]
+    //   authority_index: u16         [10..12]
+    //   transaction_index: u32       [12..16]
+    //   ms_change_index: u32         [16..20]
+    //   bump: u8                     [20]
+    //   create_key: Pubkey           [21..53]
+    //   allow_external_execute: bool [53]
+    //   keys: Vec<Pubkey>            [54..58] length, followed by 32 bytes per key
+
+    /// Offset of the `threshold: u16` field.
+    pub const MS_THRESHOLD_OFFSET: usize = 8;
+
+    /// Offset of the `u32` length prefix of the `keys: Vec<Pubkey>` field.
+    pub const MS_KEYS_LEN_OFFSET: usize = 54;
+
+    /// Offset of the first member key.
+    pub const MS_KEYS_OFFSET: usize = 58;
+}
+
+/// Errors returned by the index program, encoded as `ProgramError::Custom`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum IndexError {
+    /// The multisig threshold is zero or larger than its member count.
+    InvalidThreshold = 0,
+    /// The index account has a layout version this build doesn't know how to read.
+    UnsupportedVersion = 1,
+    /// The account passed as the index is executable.
+    ExecutableIndex = 2,
+    /// The account passed as the index to create is neither uninitialized nor already an index.
+    IllegalIndexOwner = 3,
+}
+
+impl From<IndexError> for ProgramError {
+    fn from(e: IndexError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl TryFrom<u32> for IndexError {
+    type Error = u32;
+
+    /// Recovers the error from a `ProgramError::Custom` code, returning unknown codes as is.
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        match code {
+            0 => Ok(IndexError::InvalidThreshold),
+            1 => Ok(IndexError::UnsupportedVersion),
+            2 => Ok(IndexError::ExecutableIndex),
+            3 => Ok(IndexError::IllegalIndexOwner),
+            code => Err(code),
+        }
+    }
+}
+
+/// Current version of the index account layout.
+pub const INDEX_ACCOUNT_VERSION: u8 = 1;
+
+/// Offset of the multisig pubkey in the index account data.
+pub const INDEX_MULTISIG_OFFSET: usize = 9;
+
+/// Size of the index account data.
+pub const INDEX_ACCOUNT_SIZE: usize = 62;
+
+/// Data stored in an index account, following an 8 byte discriminator.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexAccount {
+    pub version: u8,
+    pub multisig: Pubkey,
+    pub authority_index: u32,
+    pub bump: u8,
+    pub created_slot: u64,
+    pub updated_slot: u64,
+}
+
+impl IndexAccount {
+    /// Anchor-style discriminator: the first 8 bytes of `sha256("account:IndexAccount")`.
+    pub const DISCRIMINATOR: [u8; 8] = [74, 51, 72, 131, 206, 181, 217, 145];
+
+    pub fn new(multisig: Pubkey, authority_index: u32, bump: u8, created_slot: u64) -> Self {
+        Self {
+            version: INDEX_ACCOUNT_VERSION,
+            multisig,
+            authority_index,
+            bump,
+            created_slot,
+            updated_slot: 0,
+        }
+    }
+
+    /// Deserializes an index account, checking its discriminator and version. Data written with
+    /// another layout version fails with [`IndexError::UnsupportedVersion`] rather than being
+    /// misread, since later versions may lay out the fields after the version byte differently.
+    pub fn try_from_account_data(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < 9 || data[..8] != Self::DISCRIMINATOR {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if data[8] != INDEX_ACCOUNT_VERSION {
+            return Err(IndexError::UnsupportedVersion.into());
+        }
+        if data.len() < INDEX_ACCOUNT_SIZE {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Serializes the account, discriminator included, into `data`.
+    pub fn write_to(&self, data: &mut [u8]) -> ProgramResult {
+        if data.len() < INDEX_ACCOUNT_SIZE {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        data[..8].copy_from_slice(&Self::DISCRIMINATOR);
+        self.serialize(&mut &mut data[8..])
+            .map_err(|_| ProgramError::InvalidAccountData)
+    }
+}
+
+/// Instructions supported by the index program, Borsh encoded with a one byte tag. Empty
+/// instruction data is treated as `CreateIndex` for clients that predate the tag.
+///
+/// `CreateIndex`, `CreateVaultIndex`, `UpdateIndex` and `MigrateIndex` take the same accounts:
+///
+/// 0. `[]` System program
+/// 1. `[]` Squads V3 authority
+/// 2. `[]` Squads V3 multisig the authority is derived from
+/// 3. `[signer, writable]` Payer
+/// 4. `[writable]` Index PDA derived from `[authority]`
+/// 5. `[signer, writable]` Optional rent payer that funds the index account when creating or
+///    migrating it. The payer funds it when this account is absent.
+///
+/// `CloseIndex` takes:
+///
+/// 0. `[signer]` Squads V3 authority
+/// 1. `[writable]` Index PDA derived from `[authority]`
+/// 2. `[writable]` Recipient of the index account's lamports
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexInstruction {
+    /// Creates the index account for the authority.
+    CreateIndex,
+    /// Re-validates the multisig stored in an existing index account and records the slot in
+    /// `updated_slot`. It can't point the index at another multisig: the index address is derived
+    /// from the authority, which is derived from the multisig and authority index.
+    UpdateIndex,
+    /// Closes the index account, sending its lamports to the recipient. Must be signed by the
+    /// authority itself, i.e. executed as a Squads V3 multisig transaction.
+    CloseIndex,
+    /// Creates the index account for the authority (vault) at `authority_index` of the multisig.
+    /// `CreateIndex` is equivalent to `authority_index: 1`.
+    CreateVaultIndex { authority_index: u32 },
+    /// Migrates a legacy index account, created empty before the account stored any data, to the
+    /// current layout: reallocates it, tops up its rent and records the multisig and bump.
+    /// Legacy indexes could only be created for the authority at index 1. Does nothing if the
+    /// account is already migrated.
+    MigrateIndex,
+}
+
+impl IndexInstruction {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.is_empty() {
+            return Ok(Self::CreateIndex);
+        }
+        Self::try_from_slice(data).map_err(|_| ProgramError::InvalidInstructionData)
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        // Borsh serialization into a Vec is infallible.
+        self.try_to_vec().unwrap()
+    }
 }
 
+/// Lamports required for the index account to be rent exempt.
+pub fn index_account_rent(rent: &Rent) -> u64 {
+    rent.minimum_balance(INDEX_ACCOUNT_SIZE)
+}
+
+/// Derives the Squads V3 authority (vault) PDA of `multisig` at `authority_index`, returning the
+/// address and its canonical bump. Programs controlled by a Squad use the authority at index 1 as
+/// their upgrade authority.
+pub fn derive_squads_authority(multisig: &Pubkey, authority_index: u32) -> (Pubkey, u8) {
+    derive_squads_authority_with_program_id(multisig, authority_index, &squads_mpl::id())
+}
+
+/// Like [`derive_squads_authority`], for a Squads V3 deployment at `squads_program_id`.
+pub fn derive_squads_authority_with_program_id(
+    multisig: &Pubkey,
+    authority_index: u32,
+    squads_program_id: &Pubkey,
+) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"squad",
+            multisig.as_ref(),
+            &authority_index.to_le_bytes(),
+            b"authority",
+        ],
+        squads_program_id,
+    )
+}
+
+/// Decodes a base58 pubkey at compile time. Panics, failing the build, if it isn't one.
+#[cfg(any(feature = "custom-squads-program-id", test))]
+const fn decode_pubkey(encoded: &str) -> [u8; 32] {
+    const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let encoded = encoded.as_bytes();
+    assert!(!encoded.is_empty(), "empty pubkey");
+    let mut bytes = [0; 32];
+    let mut i = 0;
+    while i < encoded.len() {
+        let mut digit = 0;
+        while digit < ALPHABET.len() && ALPHABET[digit] != encoded[i] {
+            digit += 1;
+        }
+        assert!(digit < ALPHABET.len(), "invalid base58 character in pubkey");
+        // bytes = bytes * 58 + digit, as a big endian number.
+        let mut carry = digit as u32;
+        let mut j = bytes.len();
+        while j > 0 {
+            j -= 1;
+            carry += bytes[j] as u32 * 58;
+            bytes[j] = carry as u8;
+            carry >>= 8;
+        }
+        assert!(carry == 0, "pubkey is longer than 32 bytes");
+        i += 1;
+    }
+    bytes
+}
+
+/// Positions of the accounts of `CreateIndex`, `CreateVaultIndex`, `UpdateIndex` and
+/// `MigrateIndex`, as listed by [`index_instruction`]. The program reads the accounts by position,
+/// so callers building the instruction by hand, e.g. for a CPI, must pass them in this order.
+pub mod index_accounts {
+    /// The system program. Readonly.
+    pub const SYSTEM_PROGRAM: usize = 0;
+    /// The Squads authority (vault) being indexed, derived from the multisig. Readonly.
+    pub const AUTHORITY: usize = 1;
+    /// The Squads V3 multisig the authority is derived from. Readonly.
+    pub const MULTISIG: usize = 2;
+    /// Pays for the transaction, and for the rent without a rent payer. Signer and writable.
+    pub const PAYER: usize = 3;
+    /// The index PDA derived from the authority. Writable.
+    pub const INDEX: usize = 4;
+    /// Optional account that funds the rent in place of the payer. Signer and writable.
+    pub const RENT_PAYER: usize = 5;
+    /// Number of accounts that must be passed.
+    pub const REQUIRED: usize = 5;
+}
+
+/// Positions of the accounts of `CloseIndex`, as listed by [`close_instruction`].
+pub mod close_accounts {
+    /// The Squads authority (vault) whose index is closed. Signer.
+    pub const AUTHORITY: usize = 0;
+    /// The index PDA derived from the authority. Writable.
+    pub const INDEX: usize = 1;
+    /// Receives the lamports of the index account. Writable.
+    pub const RECIPIENT: usize = 2;
+    /// Number of accounts that must be passed.
+    pub const REQUIRED: usize = 3;
+}
+
+/// Derives the index PDA for `authority`, returning the address and its canonical bump.
+pub fn find_index_address(authority: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[authority.as_ref()], program_id)
+}
+
+/// Builds a `CreateIndex`, `CreateVaultIndex`, `UpdateIndex` or `MigrateIndex` instruction for
+/// `authority`, which must be derived from `multisig`. The rent payer, if any, is appended as the
+/// optional sixth account.
+pub fn index_instruction(
+    instruction: IndexInstruction,
+    program_id: Pubkey,
+    authority: &Pubkey,
+    multisig: &Pubkey,
+    payer: &Pubkey,
+    rent_payer: Option<&Pubkey>,
+) -> Instruction {
+    let index_key = find_index_address(authority, &program_id).0;
+    let mut accounts = vec![
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new_readonly(*multisig, false),
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(index_key, false),
+    ];
+    if let Some(rent_payer) = rent_payer {
+        accounts.push(AccountMeta::new(*rent_payer, true));
+    }
+    Instruction {
+        program_id,
+        accounts,
+        data: instruction.pack(),
+    }
+}
+
+/// Builds the `CloseIndex` instruction for `authority`, refunding the index rent to `recipient`.
+/// The authority must sign, which for a Squads authority means the instruction has to be executed
+/// by the multisig.
+pub fn close_instruction(
+    program_id: Pubkey,
+    authority: &Pubkey,
+    recipient: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(find_index_address(authority, &program_id).0, false),
+            AccountMeta::new(*recipient, false),
+        ],
+        data: IndexInstruction::CloseIndex.pack(),
+    }
+}
+
+/// Returns `NotEnoughAccountKeys` instead of panicking when fewer than `required` accounts are
+/// passed.
+fn check_account_count(accounts: &[AccountInfo], required: usize) -> ProgramResult {
+    assert_with_msg(
+        accounts.len() >= required,
+        ProgramError::NotEnoughAccountKeys,
+        "Not enough accounts",
+    )
+}
+
+/// Re-derives the index PDA for `authority` from a known bump, skipping the bump search.
+pub fn create_index_address(
+    authority: &Pubkey,
+    bump: u8,
+    program_id: &Pubkey,
+) -> Result<Pubkey, PubkeyError> {
+    Pubkey::create_program_address(&[authority.as_ref(), &[bump]], program_id)
+}
+
+/// Byte patterns, as `(offset, bytes)` pairs, that select index accounts in a
+/// `getProgramAccounts` memcmp filter. The discriminator is always matched; pass `multisig` to
+/// only match indexes pointing at that multisig.
+pub fn index_memcmp_filters(multisig: Option<&Pubkey>) -> Vec<(usize, Vec<u8>)> {
+    let mut filters = vec![(0, IndexAccount::DISCRIMINATOR.to_vec())];
+    if let Some(multisig) = multisig {
+        filters.push((INDEX_MULTISIG_OFFSET, multisig.to_bytes().to_vec()));
+    }
+    filters
+}
+
+/// Reads the canonical bump seed stored in an index account's data.
+pub fn read_index_bump(data: &[u8]) -> Result<u8, ProgramError> {
+    Ok(IndexAccount::try_from_account_data(data)?.bump)
+}
+
+#[cfg(all(feature = "test-bypass-discriminator", not(debug_assertions)))]
+compile_error!("the `test-bypass-discriminator` feature must not be enabled in release builds");
+
 #[cfg(not(feature = "no-entrypoint"))]
 solana_program::entrypoint!(process_instruction);
 
@@ -34,13 +433,32 @@ pub fn assert_with_msg(v: bool, err: impl Into<ProgramError>, msg: &str) -> Prog
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    _instruction_data: &[u8],
+    instruction_data: &[u8],
 ) -> ProgramResult {
-    let system_program = &accounts[0];
-    let authority = &accounts[1];
-    let multisig = &accounts[2];
-    let payer = &accounts[3];
-    let index = &accounts[4];
+    match IndexInstruction::unpack(instruction_data)? {
+        IndexInstruction::CreateIndex => process_create_index(program_id, accounts, 1),
+        IndexInstruction::CreateVaultIndex { authority_index } => {
+            process_create_index(program_id, accounts, authority_index)
+        }
+        IndexInstruction::UpdateIndex => process_update_index(program_id, accounts),
+        IndexInstruction::CloseIndex => process_close_index(program_id, accounts),
+        IndexInstruction::MigrateIndex => process_migrate_index(program_id, accounts),
+    }
+}
+
+/// Validates the accounts shared by `CreateIndex` and `UpdateIndex`, checking that the authority
+/// is derived from the multisig at `authority_index`. Returns the bump of the index PDA.
+fn validate_index_accounts(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority_index: u32,
+) -> Result<u8, ProgramError> {
+    check_account_count(accounts, index_accounts::REQUIRED)?;
+    let system_program = &accounts[index_accounts::SYSTEM_PROGRAM];
+    let authority = &accounts[index_accounts::AUTHORITY];
+    let multisig = &accounts[index_accounts::MULTISIG];
+    let payer = &accounts[index_accounts::PAYER];
+    let index = &accounts[index_accounts::INDEX];
     assert_with_msg(
         *system_program.key == system_program::id(),
         ProgramError::InvalidArgument,
@@ -52,12 +470,17 @@ pub fn process_instruction(
         "Payer must be a signer and writable",
     )?;
 
-    let (index_key, bump) = Pubkey::find_program_address(&[authority.key.as_ref()], program_id);
+    let (index_key, bump) = find_index_address(authority.key, program_id);
     assert_with_msg(
         *index.key == index_key && index.is_writable,
         ProgramError::InvalidArgument,
         "Invalid index account",
     )?;
+    assert_with_msg(
+        !index.executable,
+        IndexError::ExecutableIndex,
+        "Index account must not be executable",
+    )?;
 
     assert_with_msg(
         *multisig.owner == squads_mpl::ID,
@@ -66,15 +489,7 @@ pub fn process_instruction(
     )?;
 
     // Validate the multisig authority key.
-    let (derived_authority_key, _) = Pubkey::find_program_address(
-        &[
-            b"squad",
-            multisig.key.as_ref(),
-            &1_u32.to_le_bytes(), // Authority index should just be 1
-            b"authority",
-        ],
-        &squads_mpl::id(),
-    );
+    let (derived_authority_key, _) = derive_squads_authority(multisig.key, authority_index);
     assert_with_msg(
         *authority.key == derived_authority_key,
         ProgramError::InvalidArgument,
@@ -83,49 +498,386 @@ pub fn process_instruction(
 
     // Validate the multisig account data.
     let bytes = multisig.data.borrow();
-    let mut disc = [0_u8; 8];
-    disc.copy_from_slice(&bytes[..8]);
     assert_with_msg(
-        // This is the Anchor discriminant of the Squads V3 Multisig account.
-        [70, 118, 9, 108, 254, 215, 31, 120] == disc,
-        ProgramError::InvalidArgument,
-        "Discriminator mismatch",
+        bytes.len() >= squads_mpl::MS_KEYS_OFFSET,
+        ProgramError::InvalidAccountData,
+        "Multisig account data is too small",
     )?;
+    // Local fixtures built with `test-bypass-discriminator` only skip this check;
+    // the owner, size, threshold and authority checks still apply.
+    #[cfg(not(feature = "test-bypass-discriminator"))]
+    {
+        let mut disc = [0_u8; 8];
+        disc.copy_from_slice(&bytes[..8]);
+        assert_with_msg(
+            squads_mpl::MS_DISCRIMINATOR == disc,
+            ProgramError::InvalidArgument,
+            "Discriminator mismatch",
+        )?;
+    }
 
-    if index.data_is_empty() {
-        let current_lamports = **index.try_borrow_lamports()?;
-        if current_lamports == 0 {
-            invoke_signed(
-                &system_instruction::create_account(payer.key, index.key, 890880, 0, program_id),
-                &accounts,
-                &[&[authority.key.as_ref(), &[bump]]],
-            )?;
-        } else {
-            // Fund the account for rent exemption.
-            let required_lamports = 890880_u64.saturating_sub(current_lamports);
-            if required_lamports > 0 {
-                invoke(
-                    &system_instruction::transfer(payer.key, index.key, required_lamports),
-                    &accounts,
-                )?;
-            }
-            // Allocate space.
-            invoke_signed(
-                &system_instruction::allocate(index.key, 0),
-                &[index.clone(), system_program.clone()],
-                &[&[authority.key.as_ref(), &[bump]]],
-            )?;
-            // Assign to the specified program
-            invoke_signed(
-                &system_instruction::assign(index.key, program_id),
-                &[index.clone(), system_program.clone()],
-                &[&[authority.key.as_ref(), &[bump]]],
+    // A multisig that can't reach its threshold isn't a legitimate governance authority.
+    let threshold = u16::from_le_bytes(
+        bytes[squads_mpl::MS_THRESHOLD_OFFSET..squads_mpl::MS_THRESHOLD_OFFSET + 2]
+            .try_into()
+            .unwrap(),
+    );
+    let keys_len = u32::from_le_bytes(
+        bytes[squads_mpl::MS_KEYS_LEN_OFFSET..squads_mpl::MS_KEYS_OFFSET]
+            .try_into()
+            .unwrap(),
+    );
+    assert_with_msg(
+        threshold > 0 && u32::from(threshold) <= keys_len,
+        IndexError::InvalidThreshold,
+        "Multisig threshold must be nonzero and at most the number of members",
+    )?;
+
+    Ok(bump)
+}
+
+fn process_create_index(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    authority_index: u32,
+) -> ProgramResult {
+    let bump = validate_index_accounts(program_id, accounts, authority_index)?;
+    let system_program = &accounts[index_accounts::SYSTEM_PROGRAM];
+    let authority = &accounts[index_accounts::AUTHORITY];
+    let multisig = &accounts[index_accounts::MULTISIG];
+    let payer = &accounts[index_accounts::PAYER];
+    let index = &accounts[index_accounts::INDEX];
+
+    if index.owner == program_id {
+        msg!("Authority already indexed");
+        return Ok(());
+    }
+    // Only the system program can hand the account over below, so any other owner would fail
+    // halfway through with a less obvious error.
+    assert_with_msg(
+        *index.owner == system_program::id(),
+        IndexError::IllegalIndexOwner,
+        "Index account must be owned by the system program before it is created",
+    )?;
+
+    // A sponsor may fund the rent while the payer only covers the transaction fee.
+    let rent_payer = match accounts.get(index_accounts::RENT_PAYER) {
+        Some(rent_payer) => {
+            assert_with_msg(
+                rent_payer.is_signer && rent_payer.is_writable,
+                ProgramError::InvalidArgument,
+                "Rent payer must be a signer and writable",
             )?;
+            rent_payer
         }
+        None => payer,
+    };
+
+    let rent_lamports = index_account_rent(&Rent::get()?);
+    let current_lamports = **index.try_borrow_lamports()?;
+    if current_lamports == 0 {
+        invoke_signed(
+            &system_instruction::create_account(
+                rent_payer.key,
+                index.key,
+                rent_lamports,
+                INDEX_ACCOUNT_SIZE as u64,
+                program_id,
+            ),
+            accounts,
+            &[&[authority.key.as_ref(), &[bump]]],
+        )?;
     } else {
-        msg!("Authority already indexed");
+        // The account was funded but never initialized, e.g. by someone transferring lamports to
+        // the PDA ahead of time. Only this program can sign for the PDA and the steps below run in
+        // one instruction, so a funded system account is the only partial state an index account
+        // can be left in, and finishing it here is always safe.
+        //
+        // Fund the account for rent exemption.
+        let required_lamports = rent_lamports.saturating_sub(current_lamports);
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(rent_payer.key, index.key, required_lamports),
+                accounts,
+            )?;
+        }
+        // Allocate space.
+        invoke_signed(
+            &system_instruction::allocate(index.key, INDEX_ACCOUNT_SIZE as u64),
+            &[index.clone(), system_program.clone()],
+            &[&[authority.key.as_ref(), &[bump]]],
+        )?;
+        // Assign to the specified program
+        invoke_signed(
+            &system_instruction::assign(index.key, program_id),
+            &[index.clone(), system_program.clone()],
+            &[&[authority.key.as_ref(), &[bump]]],
+        )?;
+    }
+
+    // Record the multisig and bump so consumers don't need to re-derive them.
+    IndexAccount::new(*multisig.key, authority_index, bump, Clock::get()?.slot)
+        .write_to(&mut index.try_borrow_mut_data()?)
+}
+
+/// Re-validates the multisig and authority derivation of an existing index account and records
+/// the slot in `updated_slot`. The multisig, creation slot and authority index are preserved.
+fn process_update_index(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count(accounts, index_accounts::REQUIRED)?;
+    let multisig = &accounts[index_accounts::MULTISIG];
+    let index = &accounts[index_accounts::INDEX];
+
+    assert_with_msg(
+        index.owner == program_id,
+        ProgramError::UninitializedAccount,
+        "Authority is not indexed",
+    )?;
+
+    // The authority must still derive from the multisig at the recorded authority index. Only the
+    // stored multisig can, so this re-validates the index rather than rotating it.
+    let existing = IndexAccount::try_from_account_data(&index.try_borrow_data()?)?;
+    let bump = validate_index_accounts(program_id, accounts, existing.authority_index)?;
+    IndexAccount {
+        multisig: *multisig.key,
+        bump,
+        updated_slot: Clock::get()?.slot,
+        ..existing
+    }
+    .write_to(&mut index.try_borrow_mut_data()?)
+}
+
+/// Brings a legacy zero-length index account to the current layout. Its multisig is re-validated
+/// the same way as on creation, so the recorded fields are exactly what `CreateIndex` would have
+/// written. Legacy accounts don't record when they were created, so the slot of the migration is
+/// stored as the creation slot.
+fn process_migrate_index(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let bump = validate_index_accounts(program_id, accounts, 1)?;
+    let multisig = &accounts[index_accounts::MULTISIG];
+    let payer = &accounts[index_accounts::PAYER];
+    let index = &accounts[index_accounts::INDEX];
+
+    assert_with_msg(
+        index.owner == program_id,
+        ProgramError::UninitializedAccount,
+        "Authority is not indexed",
+    )?;
+    if !index.data_is_empty() {
+        // Fails on data that isn't an index account rather than overwriting it.
+        IndexAccount::try_from_account_data(&index.try_borrow_data()?)?;
+        msg!("Index already migrated");
         return Ok(());
     }
 
+    let rent_payer = match accounts.get(index_accounts::RENT_PAYER) {
+        Some(rent_payer) => {
+            assert_with_msg(
+                rent_payer.is_signer && rent_payer.is_writable,
+                ProgramError::InvalidArgument,
+                "Rent payer must be a signer and writable",
+            )?;
+            rent_payer
+        }
+        None => payer,
+    };
+    let required_lamports = index_account_rent(&Rent::get()?).saturating_sub(index.lamports());
+    if required_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(rent_payer.key, index.key, required_lamports),
+            accounts,
+        )?;
+    }
+    index.realloc(INDEX_ACCOUNT_SIZE, true)?;
+    IndexAccount::new(*multisig.key, 1, bump, Clock::get()?.slot)
+        .write_to(&mut index.try_borrow_mut_data()?)
+}
+
+/// Closes an index account and sends its lamports to the recipient.
+///
+/// Anyone can create an index, but removing one erases the public record that a program is
+/// governed by a multisig, so only the multisig may do it. A payer signature is not enough: any
+/// wallet could then unlink a program from its multisig and make it look like it is controlled by
+/// an unknown key, or reclaim rent it never paid. Instead the authority PDA itself must sign.
+/// Only the Squads V3 program can sign for it, and it only does so when executing a transaction
+/// that the multisig approved at its threshold. Because the index PDA is derived from the
+/// authority, that signature also proves the caller controls this specific index.
+fn process_close_index(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count(accounts, close_accounts::REQUIRED)?;
+    let authority = &accounts[close_accounts::AUTHORITY];
+    let index = &accounts[close_accounts::INDEX];
+    let recipient = &accounts[close_accounts::RECIPIENT];
+
+    assert_with_msg(
+        authority.is_signer,
+        ProgramError::MissingRequiredSignature,
+        "Authority must sign to close its index",
+    )?;
+    let (index_key, _) = find_index_address(authority.key, program_id);
+    assert_with_msg(
+        *index.key == index_key && index.is_writable,
+        ProgramError::InvalidArgument,
+        "Invalid index account",
+    )?;
+    assert_with_msg(
+        index.owner == program_id,
+        ProgramError::UninitializedAccount,
+        "Authority is not indexed",
+    )?;
+    assert_with_msg(
+        recipient.key != index.key && recipient.is_writable,
+        ProgramError::InvalidArgument,
+        "Recipient must be writable and distinct from the index account",
+    )?;
+
+    // Drain the lamports so the runtime reclaims the account at the end of the transaction.
+    let lamports = index.lamports();
+    **recipient.try_borrow_mut_lamports()? = recipient
+        .lamports()
+        .checked_add(lamports)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    **index.try_borrow_mut_lamports()? = 0;
+    index.try_borrow_mut_data()?.fill(0);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[cfg(not(feature = "custom-squads-program-id"))]
+    #[test]
+    fn test_derive_squads_authority() {
+        // Mainnet multisig and its upgrade authority vault.
+        let multisig = Pubkey::from_str("6x3BDkL2n7VjBWxRD95EsbQi2R2E4zxrvcz1VA6pihnK").unwrap();
+        let (authority, bump) = derive_squads_authority(&multisig, 1);
+        assert_eq!(
+            authority,
+            Pubkey::from_str("8mv7G3fJq5a5ej7E14vgcSGeQKH79emjU9fVfuhyitEq").unwrap()
+        );
+        assert_eq!(bump, 254);
+        assert_ne!(derive_squads_authority(&multisig, 2).0, authority);
+    }
+
+    #[test]
+    fn test_decode_pubkey() {
+        assert_eq!(
+            decode_pubkey(&squads_mpl::MAINNET_ID.to_string()),
+            squads_mpl::MAINNET_ID.to_bytes()
+        );
+        // Leading `1`s are leading zero bytes.
+        assert_eq!(decode_pubkey(&system_program::id().to_string()), [0; 32]);
+        for _ in 0..16 {
+            let key = Pubkey::new_unique();
+            assert_eq!(decode_pubkey(&key.to_string()), key.to_bytes());
+        }
+    }
+
+    #[test]
+    fn test_index_account_roundtrip() {
+        let account = IndexAccount::new(Pubkey::new_unique(), 1, 254, 42);
+        let mut data = vec![0; INDEX_ACCOUNT_SIZE];
+        account.write_to(&mut data).unwrap();
+
+        assert_eq!(data[..8], IndexAccount::DISCRIMINATOR);
+        assert_eq!(
+            data[INDEX_MULTISIG_OFFSET..INDEX_MULTISIG_OFFSET + 32],
+            account.multisig.to_bytes()
+        );
+        let decoded = IndexAccount::try_from_account_data(&data).unwrap();
+        assert_eq!(decoded, account);
+        assert_eq!(decoded.created_slot, 42);
+        assert_eq!(decoded.updated_slot, 0);
+        assert_eq!(read_index_bump(&data).unwrap(), 254);
+    }
+
+    #[test]
+    fn test_index_account_rejects_bad_data() {
+        let account = IndexAccount::new(Pubkey::new_unique(), 1, 255, 42);
+        let mut data = vec![0; INDEX_ACCOUNT_SIZE];
+        account.write_to(&mut data).unwrap();
+
+        assert!(IndexAccount::try_from_account_data(&[]).is_err());
+        assert!(IndexAccount::try_from_account_data(&data[..INDEX_ACCOUNT_SIZE - 1]).is_err());
+
+        let mut bad_discriminator = data.clone();
+        bad_discriminator[0] ^= 1;
+        assert!(IndexAccount::try_from_account_data(&bad_discriminator).is_err());
+
+        let mut bad_version = data.clone();
+        bad_version[8] = INDEX_ACCOUNT_VERSION + 1;
+        assert_eq!(
+            IndexAccount::try_from_account_data(&bad_version),
+            Err(IndexError::UnsupportedVersion.into())
+        );
+        // A future version may be shorter or longer than this one.
+        assert_eq!(
+            IndexAccount::try_from_account_data(&bad_version[..9]),
+            Err(IndexError::UnsupportedVersion.into())
+        );
+    }
+
+    #[test]
+    fn test_index_error_codes() {
+        for error in [
+            IndexError::InvalidThreshold,
+            IndexError::UnsupportedVersion,
+            IndexError::ExecutableIndex,
+            IndexError::IllegalIndexOwner,
+        ] {
+            assert_eq!(IndexError::try_from(error as u32), Ok(error));
+        }
+        assert_eq!(IndexError::try_from(4), Err(4));
+    }
+
+    #[test]
+    fn test_instruction_unpack() {
+        assert_eq!(
+            IndexInstruction::unpack(&[]).unwrap(),
+            IndexInstruction::CreateIndex
+        );
+        for ix in [
+            IndexInstruction::CreateIndex,
+            IndexInstruction::UpdateIndex,
+            IndexInstruction::CloseIndex,
+            IndexInstruction::CreateVaultIndex { authority_index: 3 },
+            IndexInstruction::MigrateIndex,
+        ] {
+            assert_eq!(IndexInstruction::unpack(&ix.pack()).unwrap(), ix);
+        }
+        assert!(IndexInstruction::unpack(&[42]).is_err());
+        // The authority index is a little endian u32 following the tag.
+        assert_eq!(
+            IndexInstruction::CreateVaultIndex { authority_index: 3 }.pack(),
+            [3, 3, 0, 0, 0]
+        );
+        assert!(IndexInstruction::unpack(&[3, 3]).is_err());
+        // New instructions are appended so existing tags keep their meaning.
+        assert_eq!(IndexInstruction::MigrateIndex.pack(), [4]);
+    }
+
+    #[test]
+    fn test_index_memcmp_filters() {
+        let account = IndexAccount::new(Pubkey::new_unique(), 1, 255, 42);
+        let mut data = vec![0; INDEX_ACCOUNT_SIZE];
+        account.write_to(&mut data).unwrap();
+
+        let matches = |filters: Vec<(usize, Vec<u8>)>| {
+            filters
+                .iter()
+                .all(|(offset, bytes)| data[*offset..*offset + bytes.len()] == bytes[..])
+        };
+        assert!(matches(index_memcmp_filters(None)));
+        assert!(matches(index_memcmp_filters(Some(&account.multisig))));
+        assert!(!matches(index_memcmp_filters(Some(&Pubkey::new_unique()))));
+    }
+
+    #[test]
+    fn test_write_to_rejects_small_buffer() {
+        let account = IndexAccount::new(Pubkey::new_unique(), 1, 255, 42);
+        let mut data = vec![0; INDEX_ACCOUNT_SIZE - 1];
+        assert_eq!(
+            account.write_to(&mut data),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+    }
+}
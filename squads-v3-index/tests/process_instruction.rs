@@ -0,0 +1,762 @@
+use solana_program::{
+    bpf_loader,
+    instruction::{Instruction, InstructionError},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use squads_v3_index::{
+    close_accounts, close_instruction, derive_squads_authority, find_index_address,
+    index_account_rent, index_accounts, index_instruction, squads_mpl, IndexAccount, IndexError,
+    IndexInstruction, INDEX_ACCOUNT_SIZE,
+};
+
+fn authority_address(multisig: &Pubkey, authority_index: u32) -> Pubkey {
+    derive_squads_authority(multisig, authority_index).0
+}
+
+/// Builds the data of a Squads V3 `Ms` account with a 1 of `members` threshold.
+fn multisig_data(members: u32) -> Vec<u8> {
+    let mut data = squads_mpl::MS_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&1_u16.to_le_bytes()); // threshold
+    data.extend_from_slice(&1_u16.to_le_bytes()); // authority_index
+    data.extend_from_slice(&0_u32.to_le_bytes()); // transaction_index
+    data.extend_from_slice(&0_u32.to_le_bytes()); // ms_change_index
+    data.push(255); // bump
+    data.extend_from_slice(Pubkey::new_unique().as_ref()); // create_key
+    data.push(0); // allow_external_execute
+    data.extend_from_slice(&members.to_le_bytes());
+    for _ in 0..members {
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+    }
+    data
+}
+
+fn multisig_account(owner: Pubkey, data: Vec<u8>) -> Account {
+    Account {
+        lamports: 1_000_000_000,
+        data,
+        owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn program_test() -> ProgramTest {
+    ProgramTest::new(
+        "squads_v3_index",
+        squads_v3_index::id(),
+        processor!(squads_v3_index::process_instruction),
+    )
+}
+
+async fn setup(multisig: Pubkey, account: Account) -> ProgramTestContext {
+    let mut program_test = program_test();
+    program_test.add_account(multisig, account);
+    program_test.start_with_context().await
+}
+
+/// Starts the program with `multisig` and `index` already at the index address of `authority`.
+async fn setup_with_index(
+    multisig: Pubkey,
+    authority: &Pubkey,
+    index: Account,
+) -> ProgramTestContext {
+    let index_key = find_index_address(authority, &squads_v3_index::id()).0;
+    let mut program_test = program_test();
+    program_test.add_account(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    );
+    program_test.add_account(index_key, index);
+    program_test.start_with_context().await
+}
+
+/// Starts the program with `multisig` and a system-owned, empty account holding `lamports` at the
+/// index address of `authority`, as left by someone transferring to the PDA ahead of time.
+async fn setup_prefunded(
+    multisig: Pubkey,
+    authority: &Pubkey,
+    lamports: u64,
+) -> ProgramTestContext {
+    let index = Account {
+        lamports,
+        data: vec![],
+        owner: system_program::id(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    setup_with_index(multisig, authority, index).await
+}
+
+/// Starts the program with `multisig` and a legacy index account of `authority`: owned by the
+/// program, empty, and holding the rent of an empty account.
+async fn setup_legacy(multisig: Pubkey, authority: &Pubkey) -> ProgramTestContext {
+    let index = Account {
+        lamports: 890_880,
+        data: vec![],
+        owner: squads_v3_index::id(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    setup_with_index(multisig, authority, index).await
+}
+
+/// Starts the program with an existing index account for `authority`.
+async fn setup_indexed(authority: &Pubkey) -> ProgramTestContext {
+    let (index_key, bump) = find_index_address(authority, &squads_v3_index::id());
+    let mut data = vec![0; INDEX_ACCOUNT_SIZE];
+    IndexAccount::new(Pubkey::new_unique(), 1, bump, 0)
+        .write_to(&mut data)
+        .unwrap();
+    let mut program_test = program_test();
+    program_test.add_account(
+        index_key,
+        Account {
+            lamports: 1_000_000,
+            data,
+            owner: squads_v3_index::id(),
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    program_test.start_with_context().await
+}
+
+async fn process(
+    context: &mut ProgramTestContext,
+    ix: Instruction,
+) -> Result<(), TransactionError> {
+    process_signed(context, ix, &[]).await
+}
+
+async fn process_signed(
+    context: &mut ProgramTestContext,
+    ix: Instruction,
+    signers: &[&Keypair],
+) -> Result<(), TransactionError> {
+    // A fresh blockhash keeps repeated identical transactions from being deduplicated.
+    let blockhash = context.get_new_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let tx = Transaction::new_signed_with_payer(
+        &[ix],
+        Some(&context.payer.pubkey()),
+        &all_signers,
+        blockhash,
+    );
+    context
+        .banks_client
+        .process_transaction(tx)
+        .await
+        .map_err(|e| e.unwrap())
+}
+
+fn instruction_error(error: InstructionError) -> TransactionError {
+    TransactionError::InstructionError(0, error)
+}
+
+#[tokio::test]
+async fn test_create_index() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+
+    let (index_key, bump) = find_index_address(&authority, &squads_v3_index::id());
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(index.owner, squads_v3_index::id());
+    let index_account = IndexAccount::try_from_account_data(&index.data).unwrap();
+    assert_eq!(index_account.multisig, multisig);
+    assert_eq!(index_account.authority_index, 1);
+    assert_eq!(index_account.bump, bump);
+    assert_eq!(index_account.updated_slot, 0);
+}
+
+#[tokio::test]
+async fn test_create_index_already_indexed() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix.clone()).await.unwrap();
+    let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+    let before = context.banks_client.get_account(index_key).await.unwrap();
+
+    // Indexing again is a no-op rather than an error.
+    process(&mut context, ix).await.unwrap();
+    let after = context.banks_client.get_account(index_key).await.unwrap();
+    assert_eq!(before, after);
+}
+
+#[tokio::test]
+async fn test_create_index_rejects_wrong_owner() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(Pubkey::new_unique(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::IllegalOwner)
+    );
+}
+
+#[cfg(not(feature = "test-bypass-discriminator"))]
+#[tokio::test]
+async fn test_create_index_rejects_bad_discriminator() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut data = multisig_data(3);
+    data[0] ^= 1;
+    let mut context = setup(multisig, multisig_account(squads_mpl::id(), data)).await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::InvalidArgument)
+    );
+}
+
+#[cfg(feature = "test-bypass-discriminator")]
+#[tokio::test]
+async fn test_create_index_accepts_mock_discriminator() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut data = multisig_data(3);
+    data[..8].fill(0);
+    let mut context = setup(multisig, multisig_account(squads_mpl::id(), data)).await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_create_index_rejects_authority_mismatch() {
+    let multisig = Pubkey::new_unique();
+    // `CreateIndex` only indexes the authority at index 1.
+    let authority = authority_address(&multisig, 2);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::InvalidArgument)
+    );
+}
+
+#[tokio::test]
+async fn test_create_index_rejects_missing_accounts() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let mut ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    ix.accounts.truncate(index_accounts::REQUIRED - 1);
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::NotEnoughAccountKeys)
+    );
+}
+
+#[tokio::test]
+async fn test_create_vault_index() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 2);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateVaultIndex { authority_index: 3 },
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::InvalidArgument)
+    );
+
+    let ix = index_instruction(
+        IndexInstruction::CreateVaultIndex { authority_index: 2 },
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+
+    let (index_key, _) = find_index_address(&authority, &squads_v3_index::id());
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    let index_account = IndexAccount::try_from_account_data(&index.data).unwrap();
+    assert_eq!(index_account.multisig, multisig);
+    assert_eq!(index_account.authority_index, 2);
+}
+
+#[tokio::test]
+async fn test_create_index_tops_up_prefunded_account() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup_prefunded(multisig, &authority, 1_000).await;
+    let rent_lamports = index_account_rent(&context.banks_client.get_rent().await.unwrap());
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+
+    // The payer only covers the difference, so the account ends up exactly rent exempt.
+    let (index_key, bump) = find_index_address(&authority, &squads_v3_index::id());
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(index.owner, squads_v3_index::id());
+    assert_eq!(index.lamports, rent_lamports);
+    assert_eq!(index.data.len(), INDEX_ACCOUNT_SIZE);
+    let index_account = IndexAccount::try_from_account_data(&index.data).unwrap();
+    assert_eq!(index_account.multisig, multisig);
+    assert_eq!(index_account.authority_index, 1);
+    assert_eq!(index_account.bump, bump);
+}
+
+#[tokio::test]
+async fn test_create_index_keeps_overfunded_account() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let lamports = 10_000_000_000;
+    let mut context = setup_prefunded(multisig, &authority, lamports).await;
+    let rent_lamports = index_account_rent(&context.banks_client.get_rent().await.unwrap());
+    assert!(lamports > rent_lamports);
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+
+    // Nothing is transferred, the account is only allocated and assigned.
+    let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(index.owner, squads_v3_index::id());
+    assert_eq!(index.lamports, lamports);
+    assert_eq!(index.data.len(), INDEX_ACCOUNT_SIZE);
+    assert!(IndexAccount::try_from_account_data(&index.data).is_ok());
+}
+
+#[tokio::test]
+async fn test_create_index_rejects_foreign_index_account() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let index = Account {
+        lamports: 10_000_000,
+        data: vec![1; 8],
+        owner: Pubkey::new_unique(),
+        executable: false,
+        rent_epoch: 0,
+    };
+    let mut context = setup_with_index(multisig, &authority, index).await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::Custom(
+            IndexError::IllegalIndexOwner as u32
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_create_index_rejects_executable_index_account() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let index = Account {
+        lamports: 10_000_000,
+        data: vec![1; 8],
+        owner: bpf_loader::id(),
+        executable: true,
+        rent_epoch: 0,
+    };
+    let mut context = setup_with_index(multisig, &authority, index).await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::Custom(IndexError::ExecutableIndex as u32))
+    );
+}
+
+#[tokio::test]
+async fn test_migrate_index() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup_legacy(multisig, &authority).await;
+    let rent_lamports = index_account_rent(&context.banks_client.get_rent().await.unwrap());
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::MigrateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix.clone()).await.unwrap();
+
+    let (index_key, bump) = find_index_address(&authority, &squads_v3_index::id());
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(index.owner, squads_v3_index::id());
+    assert_eq!(index.lamports, rent_lamports);
+    assert_eq!(index.data.len(), INDEX_ACCOUNT_SIZE);
+    let index_account = IndexAccount::try_from_account_data(&index.data).unwrap();
+    assert_eq!(index_account.multisig, multisig);
+    assert_eq!(index_account.authority_index, 1);
+    assert_eq!(index_account.bump, bump);
+
+    // Migrating again leaves the account untouched.
+    process(&mut context, ix).await.unwrap();
+    let migrated = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(migrated, index);
+}
+
+#[tokio::test]
+async fn test_migrate_index_rejects_other_multisig() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup_legacy(multisig, &authority).await;
+
+    // A multisig the authority isn't derived from can't be recorded for it.
+    let other = Pubkey::new_unique();
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::MigrateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &other,
+        &payer,
+        None,
+    );
+    assert!(process(&mut context, ix).await.is_err());
+    let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(index.data.is_empty());
+}
+
+#[tokio::test]
+async fn test_update_index() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+    let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+    let created = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    let created = IndexAccount::try_from_account_data(&created.data).unwrap();
+
+    context.warp_to_slot(100).unwrap();
+    let ix = index_instruction(
+        IndexInstruction::UpdateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    let updated = IndexAccount::try_from_account_data(&index.data).unwrap();
+    // Only the update slot changes, since the stored multisig is the only one that validates.
+    assert!(updated.updated_slot >= 100);
+    assert_eq!(
+        IndexAccount {
+            updated_slot: created.updated_slot,
+            ..updated
+        },
+        created
+    );
+}
+
+#[tokio::test]
+async fn test_update_index_rejects_other_multisig() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let other = Pubkey::new_unique();
+    let mut program_test = program_test();
+    program_test.add_account(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    );
+    program_test.add_account(other, multisig_account(squads_mpl::id(), multisig_data(3)));
+    let mut context = program_test.start_with_context().await;
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::CreateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    process(&mut context, ix).await.unwrap();
+
+    // A valid multisig the authority isn't derived from can't replace the stored one.
+    let ix = index_instruction(
+        IndexInstruction::UpdateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &other,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::InvalidArgument)
+    );
+    let index_key = find_index_address(&authority, &squads_v3_index::id()).0;
+    let index = context
+        .banks_client
+        .get_account(index_key)
+        .await
+        .unwrap()
+        .unwrap();
+    let index_account = IndexAccount::try_from_account_data(&index.data).unwrap();
+    assert_eq!(index_account.multisig, multisig);
+    assert_eq!(index_account.updated_slot, 0);
+}
+
+#[tokio::test]
+async fn test_update_index_requires_index() {
+    let multisig = Pubkey::new_unique();
+    let authority = authority_address(&multisig, 1);
+    let mut context = setup(
+        multisig,
+        multisig_account(squads_mpl::id(), multisig_data(3)),
+    )
+    .await;
+
+    let payer = context.payer.pubkey();
+    let ix = index_instruction(
+        IndexInstruction::UpdateIndex,
+        squads_v3_index::id(),
+        &authority,
+        &multisig,
+        &payer,
+        None,
+    );
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::UninitializedAccount)
+    );
+}
+
+#[tokio::test]
+async fn test_close_index() {
+    // The authority is a plain keypair here, standing in for a Squads authority PDA signing
+    // through a multisig transaction.
+    let authority = Keypair::new();
+    let recipient = Pubkey::new_unique();
+    let mut context = setup_indexed(&authority.pubkey()).await;
+
+    let ix = close_instruction(squads_v3_index::id(), &authority.pubkey(), &recipient);
+    process_signed(&mut context, ix, &[&authority])
+        .await
+        .unwrap();
+
+    let index_key = find_index_address(&authority.pubkey(), &squads_v3_index::id()).0;
+    assert_eq!(
+        context.banks_client.get_account(index_key).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        context.banks_client.get_balance(recipient).await.unwrap(),
+        1_000_000
+    );
+}
+
+#[tokio::test]
+async fn test_close_index_requires_authority_signature() {
+    let authority = Keypair::new();
+    let mut context = setup_indexed(&authority.pubkey()).await;
+
+    // The fee payer signing in place of the authority is not enough.
+    let payer = context.payer.pubkey();
+    let mut ix = close_instruction(squads_v3_index::id(), &authority.pubkey(), &payer);
+    ix.accounts[close_accounts::AUTHORITY].is_signer = false;
+    assert_eq!(
+        process(&mut context, ix).await.unwrap_err(),
+        instruction_error(InstructionError::MissingRequiredSignature)
+    );
+}